@@ -1,36 +1,209 @@
-use std::collections::{btree_map, BTreeMap};
+use std::{collections::BTreeMap, fmt::Display};
 
 use crate::{
+    id::{ClientId, TxId},
     input::{Input, TransactionType},
+    journal::{Ledger, Movement},
+    policy::{DefaultPolicy, Policy},
+    timestamp::Timestamp,
+    transaction::Transaction,
     FixedPoint,
 };
 
+use serde::{Deserialize, Serialize};
+
 pub struct AccountStorage<'a> {
     /// We use this to let the code start an own "connection" to the
     /// "database" and search through the history if needed to handle disputes
     #[allow(unused)]
     tx_path: &'a str,
-    accounts: BTreeMap<u16, Account>,
+    accounts: Box<dyn AccountStore + 'a>,
+
+    /// credit limits of the listed clients, given to their accounts when created
+    credit_limits: BTreeMap<ClientId, FixedPoint>,
+
+    /// the rules every account follows
+    policy: Box<dyn Policy>,
+}
+
+/// Why a store could not read or write an account
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where the accounts are kept while the engine runs, by client.
+///
+/// Accounts go in and out by value, so a backend can keep them anywhere, like the in-memory
+/// `BTreeMap` that is the default or a database
+pub trait AccountStore {
+    /// A copy of the account of the client, if it has one
+    fn load(&self, client: ClientId) -> Result<Option<Account>, StoreError>;
+
+    /// Changes the account of the client, created with `create` if it has none yet, and stores it
+    /// the way `change` leaves it, all at once or not at all. The change is made for transaction
+    /// `tx`, it only touches the transaction and dispute kept under that id
+    fn update(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        create: &mut dyn FnMut() -> Account,
+        change: &mut dyn FnMut(&mut Account),
+    ) -> Result<(), StoreError>;
+
+    /// Stores the account of the client, replacing the one it had
+    fn upsert(&mut self, client: ClientId, account: Account) -> Result<(), StoreError>;
+
+    /// Visits every account, ordered by client
+    fn for_each(&self, visit: &mut dyn FnMut(ClientId, &Account)) -> Result<(), StoreError>;
+
+    /// The transaction `tx` of the client, if it is kept to be disputed
+    fn transaction(&self, client: ClientId, tx: TxId) -> Result<Option<Input>, StoreError> {
+        Ok(self
+            .load(client)?
+            .and_then(|mut account| account.tx_history.remove(&tx)))
+    }
+}
+
+impl AccountStore for BTreeMap<ClientId, Account> {
+    fn load(&self, client: ClientId) -> Result<Option<Account>, StoreError> {
+        Ok(self.get(&client).cloned())
+    }
+
+    fn update(
+        &mut self,
+        client: ClientId,
+        _tx: TxId,
+        create: &mut dyn FnMut() -> Account,
+        change: &mut dyn FnMut(&mut Account),
+    ) -> Result<(), StoreError> {
+        change(self.entry(client).or_insert_with(create));
+        Ok(())
+    }
+
+    fn upsert(&mut self, client: ClientId, account: Account) -> Result<(), StoreError> {
+        self.insert(client, account);
+        Ok(())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(ClientId, &Account)) -> Result<(), StoreError> {
+        for (client, account) in self.iter() {
+            visit(*client, account);
+        }
+        Ok(())
+    }
+
+    fn transaction(&self, client: ClientId, tx: TxId) -> Result<Option<Input>, StoreError> {
+        Ok(self
+            .get(&client)
+            .and_then(|account| account.tx_history.get(&tx))
+            .cloned())
+    }
+}
+
+/// The funds moved by a transaction applied to the storage
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Applied {
+    /// None for transactions that do not move any funds
+    pub movement: Option<Movement>,
+    /// True when the transaction locked the account
+    pub locked: bool,
 }
 
 impl<'a> AccountStorage<'a> {
     pub fn new(tx_path: &'a str) -> Self {
+        Self::with_accounts(tx_path, BTreeMap::new())
+    }
+
+    /// Creates a storage that already contains accounts, for example from a snapshot
+    pub fn with_accounts(tx_path: &'a str, accounts: BTreeMap<ClientId, Account>) -> Self {
+        Self::with_store(tx_path, Box::new(accounts))
+    }
+
+    /// Creates a storage keeping its accounts in the store
+    pub fn with_store(tx_path: &'a str, accounts: Box<dyn AccountStore + 'a>) -> Self {
         Self {
             tx_path,
-            accounts: BTreeMap::new(),
+            accounts,
+            credit_limits: BTreeMap::new(),
+            policy: Box::new(DefaultPolicy),
+        }
+    }
+
+    /// Set the policy every account follows from now on
+    pub fn set_policy(&mut self, policy: Box<dyn Policy>) {
+        self.policy = policy;
+    }
+
+    /// Set the credit limits of the listed clients, replacing the limits of their accounts that
+    /// are already stored, like ones resumed from a snapshot
+    pub fn set_credit_limits(
+        &mut self,
+        credit_limits: BTreeMap<ClientId, FixedPoint>,
+    ) -> Result<(), StoreError> {
+        for (client, limit) in &credit_limits {
+            if let Some(mut account) = self.accounts.load(*client)? {
+                account.set_credit_limit(*limit);
+                self.accounts.upsert(*client, account)?;
+            }
         }
+        self.credit_limits = credit_limits;
+        Ok(())
+    }
+
+    /// Applies the input to the account of its client, creating the account if it does not exist
+    /// yet, and stores the account as it is afterwards
+    pub fn apply(&mut self, input: Input) -> Result<Applied, TransactionError> {
+        let client = input.client();
+        let tx = input.tx();
+        let limit = self.credit_limits.get(&client).copied();
+        let policy = self.policy.as_ref();
+
+        let mut input = Some(input);
+        let mut res = None;
+        self.accounts
+            .update(
+                client,
+                tx,
+                &mut || {
+                    let mut account = Account::new();
+                    if let Some(limit) = limit {
+                        account.set_credit_limit(limit);
+                    }
+                    account
+                },
+                &mut |account| {
+                    if let Some(input) = input.take() {
+                        res = Some(apply_to(account, input, policy));
+                    }
+                },
+            )
+            .map_err(|e| TransactionError::Storage(e.to_string()))?;
+        res.unwrap_or_else(|| Err(TransactionError::Storage("account not changed".to_string())))
     }
 
-    pub fn entry(&mut self, client: u16) -> btree_map::Entry<'_, u16, Account> {
-        self.accounts.entry(client)
+    /// Get the store the accounts are kept in
+    pub fn accounts(&self) -> &dyn AccountStore {
+        self.accounts.as_ref()
     }
 
-    /// Get a reference to the account storage's accounts.
-    pub fn accounts(&self) -> &BTreeMap<u16, Account> {
-        &self.accounts
+    /// Get a copy of the account of the client, if it has one
+    pub fn account(&self, client: ClientId) -> Result<Option<Account>, StoreError> {
+        self.accounts.load(client)
     }
 }
 
+/// Applies the input to the account, noting whether it locked the account
+fn apply_to(
+    account: &mut Account,
+    input: Input,
+    policy: &dyn Policy,
+) -> Result<Applied, TransactionError> {
+    let was_locked = account.locked();
+    let movement = account.handle_transaction_with(input, policy)?;
+    Ok(Applied {
+        movement,
+        locked: !was_locked && account.locked(),
+    })
+}
+
 #[derive(Debug)]
 pub enum TransactionError {
     /// There was not enough funds on the account to  handle the requested transaction
@@ -49,9 +222,63 @@ pub enum TransactionError {
     DisputeAlreadyExist,
     /// The Dispute has already been resolved one way or another
     DisputeAlreadyHandled,
+    /// The timestamp was earlier than a previously applied transaction for the same client
+    TimestampOutOfOrder,
+    /// The transaction to dispute is older than the allowed dispute window
+    DisputeWindowExpired,
+    /// Withdrawals are not allowed while the account has an open dispute
+    DisputeInProgress,
+    /// The account could not be read from or written to the store
+    Storage(String),
 }
 
-#[derive(PartialEq, Eq)]
+impl TransactionError {
+    /// A short name for the error that stays the same between versions, for files read by other
+    /// tools
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::NotEnoughAvailableFunds => "not_enough_available_funds",
+            TransactionError::MissingTxId => "missing_tx_id",
+            TransactionError::AccountLocked => "account_locked",
+            TransactionError::InvalidTx => "invalid_tx",
+            TransactionError::InvalidTxForDispute => "invalid_tx_for_dispute",
+            TransactionError::MissingDisputeTx => "missing_dispute_tx",
+            TransactionError::DisputeAlreadyExist => "dispute_already_exists",
+            TransactionError::DisputeAlreadyHandled => "dispute_already_handled",
+            TransactionError::TimestampOutOfOrder => "timestamp_out_of_order",
+            TransactionError::DisputeWindowExpired => "dispute_window_expired",
+            TransactionError::DisputeInProgress => "dispute_in_progress",
+            TransactionError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::NotEnoughAvailableFunds => write!(f, "not enough available funds"),
+            TransactionError::MissingTxId => write!(f, "transaction not found"),
+            TransactionError::AccountLocked => write!(f, "account is locked"),
+            TransactionError::InvalidTx => write!(f, "invalid transaction"),
+            TransactionError::InvalidTxForDispute => {
+                write!(f, "transaction can not be disputed")
+            }
+            TransactionError::MissingDisputeTx => write!(f, "transaction is not disputed"),
+            TransactionError::DisputeAlreadyExist => write!(f, "dispute already started"),
+            TransactionError::DisputeAlreadyHandled => write!(f, "dispute already handled"),
+            TransactionError::TimestampOutOfOrder => {
+                write!(f, "timestamp before the last transaction of the client")
+            }
+            TransactionError::DisputeWindowExpired => write!(f, "dispute window expired"),
+            TransactionError::DisputeInProgress => write!(f, "dispute in progress"),
+            TransactionError::Storage(e) => write!(f, "could not store account: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DisputeState {
     Started,
     Reimbursed,
@@ -64,22 +291,29 @@ impl DisputeState {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Account {
     /// amount of usable funds for withdrawal, trading, etc
-    available: FixedPoint,
+    pub(crate) available: FixedPoint,
     /// amount of held funds for dispute
-    held: FixedPoint,
+    pub(crate) held: FixedPoint,
     /// is the account locked or not
-    locked: bool,
+    pub(crate) locked: bool,
 
     /// Just store an entire history of each transaction performed
-    tx_history: BTreeMap<u32, Input>,
+    pub(crate) tx_history: BTreeMap<TxId, Input>,
 
     /// disputes
-    disputes: BTreeMap<u32, DisputeState>,
+    pub(crate) disputes: BTreeMap<TxId, DisputeState>,
+
+    /// timestamp of the latest applied transaction, used to enforce ordering
+    pub(crate) last_timestamp: Option<Timestamp>,
+
+    /// how far below zero the available funds are allowed to go
+    pub(crate) credit_limit: FixedPoint,
 }
 
-impl<'a> Account {
+impl Account {
     /// Generates a new empty Account
     pub fn new() -> Self {
         Account {
@@ -88,8 +322,11 @@ impl<'a> Account {
             locked: false,
             disputes: BTreeMap::new(),
             tx_history: BTreeMap::new(),
+            last_timestamp: None,
+            credit_limit: FixedPoint::from_f64(0.0),
         }
     }
+
     /// available
     pub fn available(&self) -> FixedPoint {
         self.available
@@ -103,15 +340,61 @@ impl<'a> Account {
         self.held + self.available
     }
 
+    /// Get the account's credit limit
+    pub fn credit_limit(&self) -> FixedPoint {
+        self.credit_limit
+    }
+
+    /// Set the account's credit limit, lowering it below the credit currently in use
+    /// does not touch the balances, but blocks any further use of the credit line
+    pub fn set_credit_limit(&mut self, limit: FixedPoint) {
+        self.credit_limit = limit;
+    }
+
+    /// The amount of the credit line currently in use, which is how far below zero available is
+    pub fn credit_used(&self) -> FixedPoint {
+        let zero = FixedPoint::from_f64(0.0);
+        if self.available < zero {
+            zero - self.available
+        } else {
+            zero
+        }
+    }
+
+    /// Whether `amount` can be taken from the available funds without exceeding the credit line
+    fn can_cover(&self, amount: FixedPoint) -> bool {
+        self.available - amount >= FixedPoint::from_f64(0.0) - self.credit_limit
+    }
+
     fn lock(&mut self) {
         self.locked = true;
     }
 
-    pub fn handle_transaction(&mut self, transaction: Input) -> Result<(), TransactionError> {
-        if !transaction.valid() {
-            return Err(TransactionError::InvalidTx);
+    /// Applies the transaction to the account, returning the movement of funds it caused, if any
+    pub fn handle_transaction(
+        &mut self,
+        transaction: Input,
+    ) -> Result<Option<Movement>, TransactionError> {
+        self.handle_transaction_with(transaction, &DefaultPolicy)
+    }
+
+    /// Applies the transaction to the account following the rules of the policy
+    pub fn handle_transaction_with(
+        &mut self,
+        transaction: Input,
+        policy: &dyn Policy,
+    ) -> Result<Option<Movement>, TransactionError> {
+        // Checks the input is valid for its type
+        let checked = Transaction::try_from(transaction.clone())?;
+        // Transactions without a timestamp are allowed anywhere, but those that have one
+        // can never go back in time for a single client
+        let timestamp = transaction.timestamp();
+        if let (Some(timestamp), Some(last)) = (timestamp, self.last_timestamp) {
+            if timestamp < last {
+                return Err(TransactionError::TimestampOutOfOrder);
+            }
         }
-        if self.locked {
+        if self.locked && !policy.allowed_when_locked(*transaction.r#type()) {
             // This is probably a much more complex case, since an account probably can have multiple
             // active disputes. But I also feel like trying to handle this without careful consideration
             // could be quite exploitable, which is unwanted. So I'll play it safe here, and just not handle more transactions
@@ -119,52 +402,77 @@ impl<'a> Account {
             return Err(TransactionError::AccountLocked);
         }
 
-        let tx_res = match transaction.r#type() {
-            TransactionType::Deposit => {
-                // Safe because of the validity check on the transaction
-                let amount = transaction.amount_as_fp().unwrap();
-                self.deposit(amount);
+        let res = match checked {
+            Transaction::Deposit(deposit) => {
+                let movement = self.deposit(deposit.amount);
 
                 //TODO: dont store them all, just search through the file instead
-                self.tx_history.insert(transaction.tx(), transaction);
+                self.tx_history.insert(deposit.tx, transaction);
 
-                Ok(())
+                Ok(Some(movement))
             }
-            TransactionType::Withdrawal => {
-                // Safe because of the validity check on the transaction
-                let amount = transaction.amount_as_fp().unwrap();
-                self.withdraw(amount)
+            Transaction::Withdrawal(withdrawal) => {
+                if !policy.withdraw_during_dispute() && self.has_open_dispute() {
+                    return Err(TransactionError::DisputeInProgress);
+                }
+                let movement = self.withdraw(withdrawal.amount)?;
+
+                // Withdrawals are only kept if they can ever be disputed
+                if policy.disputable(TransactionType::Withdrawal) {
+                    self.tx_history.insert(withdrawal.tx, transaction);
+                }
+                Ok(Some(movement))
             }
-            TransactionType::Dispute => {
+            Transaction::Dispute(dispute) => {
                 // we need to look back into all of the history related to this client ( and this client only ),
                 // to validate wheter the TX exists, and then we need to hold the amount found in that tx
-                self.dispute(transaction.tx())
+                self.dispute(dispute.tx, transaction.timestamp(), policy)
+                    .map(Some)
             }
-            TransactionType::Resolve => {
+            Transaction::Resolve(resolve) => {
                 // We shall unlock the held funds, if the held funds exist ofcourse
                 // If the held funds are already spent, for example by a withdrawal, then a dispute
-                self.resolve(transaction.tx())
+                self.resolve(resolve.tx).map(Some)
+            }
+            Transaction::Chargeback(chargeback) => self.chargeback(chargeback.tx, policy),
+            Transaction::SetLimit(set_limit) => {
+                self.set_credit_limit(set_limit.limit);
+                Ok(None)
             }
-            TransactionType::Chargeback => self.chargeback(transaction.tx()),
         };
 
-        tx_res
+        // Only an applied transaction moves the clock, a rejected one leaves it where it was
+        if res.is_ok() && timestamp.is_some() {
+            self.last_timestamp = timestamp;
+        }
+        res
     }
 
-    fn deposit(&mut self, amount: FixedPoint) {
+    fn deposit(&mut self, amount: FixedPoint) -> Movement {
         self.available += amount;
+        Movement::new(Ledger::Available, Ledger::External, amount)
     }
 
-    fn withdraw(&mut self, amount: FixedPoint) -> Result<(), TransactionError> {
-        if self.available >= amount {
+    fn withdraw(&mut self, amount: FixedPoint) -> Result<Movement, TransactionError> {
+        if self.can_cover(amount) {
             self.available -= amount;
-            Ok(())
+            Ok(Movement::new(Ledger::External, Ledger::Available, amount))
         } else {
             Err(TransactionError::NotEnoughAvailableFunds)
         }
     }
 
-    fn chargeback(&mut self, tx: u32) -> Result<(), TransactionError> {
+    fn has_open_dispute(&self) -> bool {
+        self.disputes
+            .values()
+            .any(|state| *state == DisputeState::Started)
+    }
+
+    fn chargeback(
+        &mut self,
+        tx: TxId,
+        policy: &dyn Policy,
+    ) -> Result<Option<Movement>, TransactionError> {
         let input = self
             .search_for_tx(tx)
             .ok_or(TransactionError::MissingTxId)?;
@@ -174,25 +482,31 @@ impl<'a> Account {
             .get_mut(&tx)
             .ok_or(TransactionError::MissingDisputeTx)?;
 
-        println!("checking dispute state input {:?}", input);
         if *dispute == DisputeState::Started {
-            println!("dispute has started");
+            let mut movement = None;
             if let Some(amount) = input.amount_as_fp() {
-                println!("the tx in question has an amount");
-                if self.held <= amount {
-                    println!("the held amount covers the dispute reimbursement");
+                if *input.r#type() == TransactionType::Withdrawal {
+                    // The withdrawal is reversed, and the client gets the held funds back
+                    self.held -= amount;
+                    self.available += amount;
+                    movement = Some(Movement::new(Ledger::Available, Ledger::Held, amount));
+                } else {
+                    // the funds were held when the dispute started, whatever else is held
                     self.held -= amount;
+                    movement = Some(Movement::new(Ledger::ChargebackLoss, Ledger::Held, amount));
                 }
             }
             *dispute = DisputeState::Reimbursed;
-            self.lock();
-            Ok(())
+            if policy.lock_on_chargeback() {
+                self.lock();
+            }
+            Ok(movement)
         } else {
             Err(TransactionError::DisputeAlreadyHandled)
         }
     }
 
-    fn resolve(&mut self, tx: u32) -> Result<(), TransactionError> {
+    fn resolve(&mut self, tx: TxId) -> Result<Movement, TransactionError> {
         let input = self
             .search_for_tx(tx)
             .ok_or(TransactionError::MissingTxId)?;
@@ -213,9 +527,14 @@ impl<'a> Account {
                     );
                 }
                 self.held = heldres;
-                self.available += amount;
                 *dispute = DisputeState::Resolved;
-                Ok(())
+                if *input.r#type() == TransactionType::Withdrawal {
+                    // The withdrawal stands, so the funds held for it leave again
+                    Ok(Movement::new(Ledger::External, Ledger::Held, amount))
+                } else {
+                    self.available += amount;
+                    Ok(Movement::new(Ledger::Available, Ledger::Held, amount))
+                }
             } else {
                 Err(TransactionError::InvalidTx)
             }
@@ -224,41 +543,66 @@ impl<'a> Account {
         }
     }
 
-    fn dispute(&mut self, tx: u32) -> Result<(), TransactionError> {
+    fn dispute(
+        &mut self,
+        tx: TxId,
+        at: Option<Timestamp>,
+        policy: &dyn Policy,
+    ) -> Result<Movement, TransactionError> {
         // Fetch the tx that is to be disputed
         let input = self
             .search_for_tx(tx)
             .ok_or(TransactionError::MissingTxId)?;
 
+        // The window can only be enforced when both the dispute and the disputed tx carry a time
+        let window = policy.dispute_window();
+        if let (Some(window), Some(at), Some(tx_time)) = (window, at, input.timestamp()) {
+            if at.millis_since(tx_time) > window {
+                return Err(TransactionError::DisputeWindowExpired);
+            }
+        }
+
+        if !policy.disputable(*input.r#type()) {
+            return Err(TransactionError::InvalidTxForDispute);
+        }
+        if self.disputes.contains_key(&tx) {
+            return Err(TransactionError::DisputeAlreadyExist);
+        }
+        let amount = input.amount_as_fp().ok_or(TransactionError::InvalidTx)?;
+
         match input.r#type() {
             TransactionType::Deposit => {
-                if self.disputes.contains_key(&tx) {
-                    Err(TransactionError::DisputeAlreadyExist)
+                // Holding funds that are already spent is allowed as long as the
+                // credit line can cover it, or the policy does not care
+                let covered = if policy.dispute_uses_credit() {
+                    self.can_cover(amount)
                 } else {
-                    if let Some(amount) = input.amount_as_fp() {
-                        if self.available() >= amount {
-                            self.disputes.insert(tx, DisputeState::new());
-                            self.available -= amount;
-                            self.held += amount;
-                            Ok(())
-                        } else {
-                            Err(TransactionError::NotEnoughAvailableFunds)
-                        }
-                    } else {
-                        Err(TransactionError::InvalidTx)
-                    }
+                    self.available >= amount
+                };
+                if covered || policy.dispute_without_funds() {
+                    self.disputes.insert(tx, DisputeState::new());
+                    self.available -= amount;
+                    self.held += amount;
+                    Ok(Movement::new(Ledger::Held, Ledger::Available, amount))
+                } else {
+                    Err(TransactionError::NotEnoughAvailableFunds)
                 }
             }
+            TransactionType::Withdrawal => {
+                // The withdrawn funds are provisionally brought back, and held until the
+                // dispute is closed
+                self.disputes.insert(tx, DisputeState::new());
+                self.held += amount;
+                Ok(Movement::new(Ledger::Held, Ledger::External, amount))
+            }
             _ => Err(TransactionError::InvalidTxForDispute),
         }
-        // store the tx under dispute, unless already handled
-        // hold the funds related in the dispute
     }
 
-    fn search_for_tx(&self, tx: u32) -> Option<Input> {
+    fn search_for_tx(&self, tx: TxId) -> Option<Input> {
         let local = self.tx_history.get(&tx);
 
-        if let Some(_) = local {
+        if local.is_some() {
             local.cloned()
         } else {
             //TODO: For future improvements, we would have to look through an external storage of TX
@@ -270,10 +614,23 @@ impl<'a> Account {
     pub fn locked(&self) -> bool {
         self.locked
     }
+
+    /// Get the state of the dispute on `tx`, if it has ever been disputed
+    pub fn dispute_state(&self, tx: TxId) -> Option<DisputeState> {
+        self.disputes.get(&tx).copied()
+    }
+
+    /// Get every disputed tx of the account together with the state of the dispute
+    pub fn disputes(&self) -> impl Iterator<Item = (TxId, DisputeState)> + '_ {
+        self.disputes.iter().map(|(tx, state)| (*tx, *state))
+    }
 }
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::policy::WithDisputeWindow;
+    use std::{cell::Cell, rc::Rc};
 
     #[tokio::test]
     async fn account_deposit_withdraw() {
@@ -383,4 +740,193 @@ mod tests {
         assert_eq!(0.0, account.total(), "total amount was wrong");
         assert_eq!(true, account.locked(), "account locked state was wrong");
     }
+
+    #[tokio::test]
+    async fn account_timestamps_non_decreasing() {
+        let mut account = Account::new();
+
+        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(50.0))
+            .with_timestamp(Timestamp::from_millis(2000));
+        assert!(account.handle_transaction(deposit).is_ok());
+
+        // going back in time is not allowed
+        let deposit = Input::new(TransactionType::Deposit, 1, 2, Some(10.0))
+            .with_timestamp(Timestamp::from_millis(1000));
+        assert!(matches!(
+            account.handle_transaction(deposit),
+            Err(TransactionError::TimestampOutOfOrder)
+        ));
+        assert_eq!(50.0, account.available());
+
+        // but the same time, or no time at all is fine
+        let deposit = Input::new(TransactionType::Deposit, 1, 3, Some(10.0))
+            .with_timestamp(Timestamp::from_millis(2000));
+        assert!(account.handle_transaction(deposit).is_ok());
+        let deposit = Input::new(TransactionType::Deposit, 1, 4, Some(10.0));
+        assert!(account.handle_transaction(deposit).is_ok());
+        assert_eq!(70.0, account.available());
+
+        // a rejected transaction does not move the clock
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 5, Some(100.0))
+            .with_timestamp(Timestamp::from_millis(5000));
+        assert!(account.handle_transaction(withdrawal).is_err());
+        let deposit = Input::new(TransactionType::Deposit, 1, 6, Some(10.0))
+            .with_timestamp(Timestamp::from_millis(3000));
+        assert!(account.handle_transaction(deposit).is_ok());
+        assert_eq!(Some(Timestamp::from_millis(3000)), account.last_timestamp);
+    }
+
+    #[tokio::test]
+    async fn account_dispute_window() {
+        let day = crate::timestamp::MILLIS_PER_DAY;
+        let policy = WithDisputeWindow {
+            policy: Box::new(DefaultPolicy),
+            dispute_window: 120 * day,
+        };
+        let mut account = Account::new();
+
+        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(50.0))
+            .with_timestamp(Timestamp::from_millis(0));
+        assert!(account.handle_transaction_with(deposit, &policy).is_ok());
+        let deposit = Input::new(TransactionType::Deposit, 1, 2, Some(5.0))
+            .with_timestamp(Timestamp::from_millis(100 * day));
+        assert!(account.handle_transaction_with(deposit, &policy).is_ok());
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None)
+            .with_timestamp(Timestamp::from_millis(121 * day));
+        assert!(matches!(
+            account.handle_transaction_with(dispute, &policy),
+            Err(TransactionError::DisputeWindowExpired)
+        ));
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None)
+            .with_timestamp(Timestamp::from_millis(121 * day));
+        assert!(account.handle_transaction_with(dispute, &policy).is_ok());
+        assert_eq!(5.0, account.held());
+        assert_eq!(50.0, account.available());
+    }
+
+    #[tokio::test]
+    async fn account_credit_line() {
+        let mut account = Account::new();
+
+        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(50.0));
+        assert!(account.handle_transaction(deposit).is_ok());
+
+        // Without a credit line, nothing can be overdrawn
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 2, Some(60.0));
+        assert!(account.handle_transaction(withdrawal).is_err());
+
+        let limit = Input::new(TransactionType::SetLimit, 1, 3, Some(20.0));
+        assert!(account.handle_transaction(limit).is_ok());
+        assert_eq!(20.0, account.credit_limit());
+
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 4, Some(60.0));
+        assert!(account.handle_transaction(withdrawal).is_ok());
+        assert_eq!(-10.0, account.available());
+        assert_eq!(10.0, account.credit_used());
+
+        // Only 10 left of the credit line
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 5, Some(10.5));
+        assert!(account.handle_transaction(withdrawal).is_err());
+
+        // The disputed deposit is already spent, and the credit line can not cover it
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        assert!(matches!(
+            account.handle_transaction(dispute),
+            Err(TransactionError::NotEnoughAvailableFunds)
+        ));
+
+        let deposit = Input::new(TransactionType::Deposit, 1, 6, Some(15.0));
+        assert!(account.handle_transaction(deposit).is_ok());
+        let dispute = Input::new(TransactionType::Dispute, 1, 6, None);
+        assert!(account.handle_transaction(dispute).is_ok());
+        assert_eq!(-10.0, account.available());
+        assert_eq!(15.0, account.held());
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 6, None);
+        assert!(account.handle_transaction(chargeback).is_ok());
+        assert_eq!(0.0, account.held());
+        assert_eq!(-10.0, account.total());
+        assert_eq!(10.0, account.credit_used());
+        assert!(account.locked());
+    }
+
+    /// Keeps the accounts in a map, counting how many it had to create
+    #[derive(Default)]
+    struct CountingStore {
+        accounts: BTreeMap<ClientId, Account>,
+        created: Rc<Cell<usize>>,
+    }
+
+    impl AccountStore for CountingStore {
+        fn load(&self, client: ClientId) -> Result<Option<Account>, StoreError> {
+            self.accounts.load(client)
+        }
+
+        fn update(
+            &mut self,
+            client: ClientId,
+            tx: TxId,
+            create: &mut dyn FnMut() -> Account,
+            change: &mut dyn FnMut(&mut Account),
+        ) -> Result<(), StoreError> {
+            if !self.accounts.contains_key(&client) {
+                self.created.set(self.created.get() + 1);
+            }
+            self.accounts.update(client, tx, create, change)
+        }
+
+        fn upsert(&mut self, client: ClientId, account: Account) -> Result<(), StoreError> {
+            self.accounts.upsert(client, account)
+        }
+
+        fn for_each(&self, visit: &mut dyn FnMut(ClientId, &Account)) -> Result<(), StoreError> {
+            self.accounts.for_each(visit)
+        }
+    }
+
+    #[tokio::test]
+    async fn storage_with_other_store() {
+        let fp = |s: &str| s.parse::<FixedPoint>().unwrap();
+        let store = CountingStore::default();
+        let created = store.created.clone();
+        let mut storage = AccountStorage::with_store("", Box::new(store));
+        storage
+            .set_credit_limits(BTreeMap::from([(ClientId(2), fp("5"))]))
+            .unwrap();
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 2, 1, Some(3.0)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(1.0)),
+            Input::new(TransactionType::Withdrawal, 2, 3, Some(6.0)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+        ];
+        for input in inputs {
+            storage.apply(input).unwrap();
+        }
+
+        let mut clients = Vec::new();
+        storage
+            .accounts()
+            .for_each(&mut |client, _| clients.push(client))
+            .unwrap();
+        assert_eq!(vec![ClientId(1), ClientId(2)], clients);
+        assert_eq!(2, created.get());
+        let account = |client| storage.account(ClientId(client)).unwrap().unwrap();
+        assert_eq!(fp("3"), account(2).credit_used());
+        assert_eq!(fp("1"), account(1).held());
+        assert_eq!(
+            Some(TransactionType::Deposit),
+            storage
+                .accounts()
+                .transaction(ClientId(1), TxId::Number(2))
+                .unwrap()
+                .map(|input| *input.r#type())
+        );
+        assert!(storage
+            .accounts()
+            .transaction(ClientId(2), TxId::Number(2))
+            .unwrap()
+            .is_none());
+    }
 }
@@ -0,0 +1,218 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Deserialize;
+
+use crate::{
+    id::{ClientId, TxId},
+    input::{Input, TransactionType},
+    timestamp::Timestamp,
+    FixedPoint,
+};
+
+/// A booked entry of a bank statement, whatever format the statement came in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The account the statement is for, as the bank identifies it, like an IBAN
+    pub account: String,
+    pub amount: FixedPoint,
+    /// True for money coming into the account
+    pub credit: bool,
+    /// True when the entry undoes an earlier one, like a returned or reversed payment
+    pub reversal: bool,
+    /// Identifies the payment, an entry undoing it carries the same reference
+    pub reference: String,
+    pub booked: Option<Timestamp>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The statement is for an account that is not mapped to a client
+    UnknownAccount(String),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::UnknownAccount(account) => {
+                write!(f, "account {} is not mapped to a client", account)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// A line of the account lookup file
+#[derive(Debug, Deserialize)]
+struct AccountLine {
+    account: String,
+    client: ClientId,
+}
+
+/// Reads which client every bank account belongs to from a csv file with the columns
+/// `account, client`
+pub fn read_accounts(pathname: &str) -> Result<BTreeMap<String, ClientId>, csv::Error> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(pathname)?;
+    let mut accounts = BTreeMap::new();
+    for line in rdr.deserialize() {
+        let line: AccountLine = line?;
+        accounts.insert(line.account, line.client);
+    }
+    Ok(accounts)
+}
+
+/// Turns statement entries into inputs for the engine.
+///
+/// The tx id of an entry is a UUID derived from its client and reference, so it is the same on
+/// every run and never clashes with the numbered tx ids of other input. An entry undoing a payment
+/// carries the reference of that payment, so it is applied to the transaction of that payment,
+/// even when the payment came in an earlier statement or an earlier run
+#[derive(Debug, Default)]
+pub struct Importer {
+    accounts: BTreeMap<String, ClientId>,
+}
+
+impl Importer {
+    pub fn new(accounts: BTreeMap<String, ClientId>) -> Self {
+        Self { accounts }
+    }
+
+    /// The inputs for a single entry. Credits are deposits and debits withdrawals. A debit undoing
+    /// a credit disputes and charges it back, a credit undoing a debit just brings the funds back
+    pub fn inputs(&self, entry: &Entry) -> Result<Vec<Input>, ImportError> {
+        let client = *self
+            .accounts
+            .get(&entry.account)
+            .ok_or_else(|| ImportError::UnknownAccount(entry.account.clone()))?;
+        let with_time = |input: Input| match entry.booked {
+            Some(booked) => input.with_timestamp(booked),
+            None => input,
+        };
+
+        let original = tx_id(client, &entry.reference, false);
+        if entry.reversal && !entry.credit {
+            return Ok(vec![
+                with_time(Input::with_ids(
+                    TransactionType::Dispute,
+                    client,
+                    original,
+                    None,
+                )),
+                with_time(Input::with_ids(
+                    TransactionType::Chargeback,
+                    client,
+                    original,
+                    None,
+                )),
+            ]);
+        }
+
+        let r#type = if entry.credit {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+        let tx = tx_id(client, &entry.reference, entry.reversal);
+        Ok(vec![with_time(Input::with_ids(
+            r#type,
+            client,
+            tx,
+            Some(entry.amount),
+        ))])
+    }
+}
+
+/// A version 8 UUID from the 128 bit FNV-1a hash of the client and reference, which is the same
+/// on every platform and build. A reversal gets an id of its own, apart from the payment it undoes
+fn tx_id(client: ClientId, reference: &str, reversal: bool) -> TxId {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let marker: &[u8] = if reversal { b"R" } else { b"P" };
+    let bytes = client.0.to_be_bytes();
+    let bytes = bytes.iter().chain(marker).chain(reference.as_bytes());
+    let hash = bytes.fold(OFFSET, |hash, byte| {
+        (hash ^ *byte as u128).wrapping_mul(PRIME)
+    });
+    let version = (hash & !(0xf << 76)) | (0x8 << 76);
+    TxId::Uuid((version & !(0x3 << 62)) | (0x2 << 62))
+}
+
+/// Parses an ISO 8601 date, or date and time with or without an offset, as used in statements.
+/// Times without an offset are taken as UTC
+pub fn parse_datetime(s: &str) -> Option<Timestamp> {
+    if let Ok(timestamp) = s.parse() {
+        return Some(timestamp);
+    }
+    let datetime = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .ok()?;
+    Some(Timestamp::from_millis(
+        datetime.and_utc().timestamp_millis(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(credit: bool, reversal: bool, reference: &str) -> Entry {
+        Entry {
+            account: "NL91ABNA0417164300".to_string(),
+            amount: "10".parse().unwrap(),
+            credit,
+            reversal,
+            reference: reference.to_string(),
+            booked: parse_datetime("2023-01-05"),
+        }
+    }
+
+    #[tokio::test]
+    async fn importer_maps_entries() {
+        let accounts = BTreeMap::from([("NL91ABNA0417164300".to_string(), ClientId(7))]);
+        let importer = Importer::new(accounts.clone());
+
+        let deposit = importer.inputs(&entry(true, false, "E2E-1")).unwrap();
+        assert_eq!(TransactionType::Deposit, *deposit[0].r#type());
+        assert_eq!(ClientId(7), deposit[0].client());
+        assert_eq!("10.0000", deposit[0].amount_as_fp().unwrap().to_string());
+        assert_eq!(
+            Some(Timestamp::from_millis(1672876800000)),
+            deposit[0].timestamp()
+        );
+        let deposit_tx = deposit[0].tx();
+        assert!(matches!(deposit_tx, TxId::Uuid(_)));
+        // a proper version 8 UUID
+        assert_eq!(Some('8'), deposit_tx.to_string().chars().nth(14));
+
+        let withdrawal = importer.inputs(&entry(false, false, "E2E-2")).unwrap();
+        assert_eq!(TransactionType::Withdrawal, *withdrawal[0].r#type());
+        assert_ne!(deposit_tx, withdrawal[0].tx());
+
+        // the returned deposit is disputed and charged back, also by an importer of a later run
+        let later = Importer::new(accounts);
+        let returned = later.inputs(&entry(false, true, "E2E-1")).unwrap();
+        let types: Vec<_> = returned.iter().map(|input| *input.r#type()).collect();
+        assert_eq!(
+            vec![TransactionType::Dispute, TransactionType::Chargeback],
+            types
+        );
+        assert!(returned.iter().all(|input| input.tx() == deposit_tx));
+
+        // a returned withdrawal brings the funds back, as a transaction of its own
+        let returned = importer.inputs(&entry(true, true, "E2E-2")).unwrap();
+        assert_eq!(TransactionType::Deposit, *returned[0].r#type());
+        assert_ne!(withdrawal[0].tx(), returned[0].tx());
+
+        let mut unknown = entry(true, false, "E2E-3");
+        unknown.account = "DE89370400440532013000".to_string();
+        assert!(matches!(
+            importer.inputs(&unknown),
+            Err(ImportError::UnknownAccount(_))
+        ));
+    }
+}
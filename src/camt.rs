@@ -0,0 +1,586 @@
+use std::{fmt::Display, io};
+
+use quick_xml::{
+    escape::resolve_predefined_entity,
+    events::{BytesDecl, BytesText, Event},
+    Reader, Writer,
+};
+
+use crate::{
+    bank::{parse_datetime, Entry},
+    history::{Balance, Booking, History},
+    id::ClientId,
+    input::TransactionType,
+    output::Precision,
+    timestamp::Timestamp,
+    FixedPoint,
+};
+
+#[derive(Debug)]
+pub enum CamtError {
+    /// The file is not well formed xml
+    Xml(quick_xml::Error),
+    /// An entry of the statement lacks an element it can not do without
+    MissingElement { entry: usize, element: &'static str },
+    /// An element of an entry holds something that can not be read
+    InvalidValue {
+        entry: usize,
+        element: &'static str,
+        value: String,
+    },
+}
+
+impl Display for CamtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CamtError::Xml(e) => write!(f, "invalid xml: {}", e),
+            CamtError::MissingElement { entry, element } => {
+                write!(f, "entry {}: missing <{}>", entry, element)
+            }
+            CamtError::InvalidValue {
+                entry,
+                element,
+                value,
+            } => write!(f, "entry {}: invalid <{}>: {}", entry, element, value),
+        }
+    }
+}
+
+impl std::error::Error for CamtError {}
+
+impl From<quick_xml::Error> for CamtError {
+    fn from(e: quick_xml::Error) -> Self {
+        CamtError::Xml(e)
+    }
+}
+
+/// The parts of an `<Ntry>` that are read, as found in the file
+#[derive(Debug, Default)]
+struct RawEntry {
+    amount: Option<String>,
+    indicator: Option<String>,
+    reversal: bool,
+    returned: bool,
+    /// `BOOK` for booked entries, as opposed to pending or informational ones
+    status: Option<String>,
+    booked: Option<String>,
+    end_to_end_id: Option<String>,
+    servicer_ref: Option<String>,
+    entry_ref: Option<String>,
+}
+
+/// What banks fill in when the payer gave no end-to-end id
+const NOT_PROVIDED: &str = "NOTPROVIDED";
+
+impl RawEntry {
+    /// The entry, or None if it is not booked yet or only informational
+    fn into_entry(
+        self,
+        index: usize,
+        account: Option<&String>,
+    ) -> Result<Option<Entry>, CamtError> {
+        let missing = |element| CamtError::MissingElement {
+            entry: index,
+            element,
+        };
+        let invalid = |element, value: &str| CamtError::InvalidValue {
+            entry: index,
+            element,
+            value: value.to_string(),
+        };
+
+        match self.status.as_deref() {
+            Some("BOOK") => {}
+            Some(_) => return Ok(None),
+            None => return Err(missing("Sts")),
+        }
+        let account = account.ok_or_else(|| missing("Acct"))?.clone();
+        let amount = self.amount.ok_or_else(|| missing("Amt"))?;
+        let amount = amount.parse().map_err(|_| invalid("Amt", &amount))?;
+        let credit = match self.indicator.as_deref() {
+            Some("CRDT") => true,
+            Some("DBIT") => false,
+            Some(other) => return Err(invalid("CdtDbtInd", other)),
+            None => return Err(missing("CdtDbtInd")),
+        };
+        let booked = match self.booked {
+            Some(booked) => {
+                Some(parse_datetime(&booked).ok_or_else(|| invalid("BookgDt", &booked))?)
+            }
+            None => None,
+        };
+        let reference = self
+            .end_to_end_id
+            .or(self.servicer_ref)
+            .or(self.entry_ref)
+            .ok_or_else(|| missing("EndToEndId"))?;
+
+        Ok(Some(Entry {
+            account,
+            amount,
+            credit,
+            reversal: self.reversal || self.returned,
+            reference,
+            booked,
+        }))
+    }
+}
+
+/// Reads the booked entries of camt.053 statements and camt.054 notifications.
+///
+/// Every `<Ntry>` with the status `BOOK` becomes an entry, pending and informational entries are
+/// skipped. An entry is referenced by its end-to-end id, falling back to the reference of the
+/// account servicer or of the entry when there is none or it is `NOTPROVIDED`. An entry marked with `<RvslInd>` or carrying
+/// return information undoes the payment with the same reference. Batched entries are read as a
+/// single payment, by the first end-to-end id in them
+pub fn parse(xml: &str) -> Result<Vec<Entry>, CamtError> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut account = None;
+    let mut entry: Option<RawEntry> = None;
+    let mut entries = Vec::new();
+    // every entry counts, also those that are skipped, so errors point at the right one
+    let mut index = 0;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                let name = start.local_name().into_inner().to_string();
+                if name == "Ntry" {
+                    entry = Some(RawEntry::default());
+                }
+                if name == "RtrInf" {
+                    if let Some(entry) = &mut entry {
+                        entry.returned = true;
+                    }
+                }
+                path.push(name);
+                text.clear();
+            }
+            Event::Text(content) => text.push_str(&content.xml10_content()),
+            Event::CData(content) => text.push_str(&content.xml10_content()),
+            Event::GeneralRef(reference) => {
+                let name = reference.xml10_content();
+                text.push_str(resolve_predefined_entity(&name).unwrap_or_default());
+            }
+            Event::End(_) => {
+                let value = text.trim().to_string();
+                text.clear();
+                let Some(name) = path.last() else {
+                    continue;
+                };
+                let parent = path.len().checked_sub(2).map(|i| path[i].as_str());
+
+                match &mut entry {
+                    Some(current) if name == "Ntry" => {
+                        let raw = std::mem::take(current);
+                        entry = None;
+                        index += 1;
+                        if let Some(booked) = raw.into_entry(index, account.as_ref())? {
+                            entries.push(booked);
+                        }
+                    }
+                    Some(current) => match (parent, name.as_str()) {
+                        (Some("Ntry"), "Amt") => current.amount = Some(value),
+                        (Some("Ntry"), "CdtDbtInd") => current.indicator = Some(value),
+                        (Some("Ntry"), "RvslInd") => current.reversal = value == "true",
+                        (Some("Ntry"), "NtryRef") => current.entry_ref = Some(value),
+                        (Some("Ntry"), "AcctSvcrRef") => current.servicer_ref = Some(value),
+                        // a code of its own in newer versions, plain text in older ones
+                        (Some("Sts"), "Cd") => current.status = Some(value),
+                        (Some("Ntry"), "Sts") if !value.is_empty() => current.status = Some(value),
+                        (Some("BookgDt"), "DtTm") | (Some("BookgDt"), "Dt") => {
+                            current.booked = Some(value)
+                        }
+                        (_, "EndToEndId") if !value.eq_ignore_ascii_case(NOT_PROVIDED) => {
+                            current.end_to_end_id.get_or_insert(value);
+                        }
+                        _ => {}
+                    },
+                    // the account of the statement, not the accounts of the parties of an entry
+                    None => {
+                        let in_account = path.len() >= 3
+                            && path[..path.len() - 1].ends_with(&["Acct".into(), "Id".into()])
+                            || path.len() >= 4
+                                && path[..path.len() - 1].ends_with(&[
+                                    "Acct".into(),
+                                    "Id".into(),
+                                    "Othr".into(),
+                                ]);
+                        if in_account && (name == "IBAN" || name == "Id") {
+                            account = Some(value);
+                        }
+                    }
+                }
+                path.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// What goes into the statements besides the history itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementOptions {
+    /// The currency code all amounts are in, the engine does not know about currencies
+    pub currency: String,
+    pub precision: Precision,
+    /// When the statements were created, which also identifies them
+    pub created: Timestamp,
+}
+
+/// Writes a camt.053 statement for every client in the history, all in a single message.
+///
+/// A statement has the opening balance, every booking changing the total funds of the client, and
+/// the closing balance, both in total and available. The held funds are given as a closing
+/// balance of the proprietary type `HELD`. Funds moved between available and held by disputes are
+/// not booked, they only show in the balances
+pub fn write_statements<W: io::Write>(
+    history: &History,
+    options: &StatementOptions,
+    writer: W,
+) -> io::Result<()> {
+    let mut xml = Writer::new_with_indent(writer, b' ', 2);
+    xml.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    let message = format!("TOYPA-{}", options.created.millis());
+    let created = options.created.to_string();
+
+    xml.create_element("Document")
+        .with_attribute(("xmlns", "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08"))
+        .write_inner_content(|xml| {
+            xml.create_element("BkToCstmrStmt")
+                .write_inner_content(|xml| {
+                    xml.create_element("GrpHdr").write_inner_content(|xml| {
+                        text(xml, "MsgId", &message)?;
+                        text(xml, "CreDtTm", &created)
+                    })?;
+                    for client in history.clients() {
+                        xml.create_element("Stmt").write_inner_content(|xml| {
+                            statement(xml, history, client, options, &message, &created)
+                        })?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    xml.into_inner().flush()
+}
+
+fn statement<W: io::Write>(
+    xml: &mut Writer<W>,
+    history: &History,
+    client: ClientId,
+    options: &StatementOptions,
+    message: &str,
+    created: &str,
+) -> io::Result<()> {
+    text(xml, "Id", &format!("{}-{}", message, client))?;
+    text(xml, "CreDtTm", created)?;
+    xml.create_element("Acct").write_inner_content(|xml| {
+        xml.create_element("Id").write_inner_content(|xml| {
+            xml.create_element("Othr")
+                .write_inner_content(|xml| text(xml, "Id", &client.to_string()))?;
+            Ok(())
+        })?;
+        text(xml, "Ccy", &options.currency)
+    })?;
+
+    let opening = history.opening(client);
+    let closing = history
+        .applied(client)
+        .iter()
+        .fold(opening, |balance, applied| applied.after(balance));
+    balance(xml, "OPBD", opening.total(), options)?;
+    balance(xml, "OPAV", opening.available, options)?;
+    balance(xml, "CLBD", closing.total(), options)?;
+    balance(xml, "CLAV", closing.available, options)?;
+    balance(xml, "HELD", closing.held, options)?;
+
+    for booking in history.bookings(client).filter(Booking::changes_total) {
+        entry(xml, &booking, options)?;
+    }
+    Ok(())
+}
+
+fn balance<W: io::Write>(
+    xml: &mut Writer<W>,
+    code: &str,
+    amount: FixedPoint,
+    options: &StatementOptions,
+) -> io::Result<()> {
+    // only the standard codes go in `Cd`, anything else is proprietary
+    let kind = if code == "HELD" { "Prtry" } else { "Cd" };
+    xml.create_element("Bal").write_inner_content(|xml| {
+        xml.create_element("Tp").write_inner_content(|xml| {
+            xml.create_element("CdOrPrtry")
+                .write_inner_content(|xml| text(xml, kind, code))?;
+            Ok(())
+        })?;
+        self::amount(xml, amount, options)?;
+        xml.create_element("Dt")
+            .write_inner_content(|xml| text(xml, "DtTm", &options.created.to_string()))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn entry<W: io::Write>(
+    xml: &mut Writer<W>,
+    booking: &Booking,
+    options: &StatementOptions,
+) -> io::Result<()> {
+    // how much the total funds of the client change, negative for a debit
+    let change = Balance::default().after(&booking.movement).total();
+    let code = match booking.r#type {
+        TransactionType::Deposit => "DEPOSIT",
+        TransactionType::Withdrawal => "WITHDRAWAL",
+        TransactionType::Chargeback => "CHARGEBACK",
+        _ => "OTHER",
+    };
+    let reference = booking.tx.to_string();
+
+    xml.create_element("Ntry").write_inner_content(|xml| {
+        text(xml, "NtryRef", &reference)?;
+        self::amount(xml, change, options)?;
+        // a chargeback undoes the deposit with the same reference
+        if booking.r#type == TransactionType::Chargeback {
+            text(xml, "RvslInd", "true")?;
+        }
+        xml.create_element("Sts")
+            .write_inner_content(|xml| text(xml, "Cd", "BOOK"))?;
+        if let Some(timestamp) = booking.timestamp {
+            xml.create_element("BookgDt")
+                .write_inner_content(|xml| text(xml, "DtTm", &timestamp.to_string()))?;
+        }
+        xml.create_element("BkTxCd").write_inner_content(|xml| {
+            xml.create_element("Prtry")
+                .write_inner_content(|xml| text(xml, "Cd", code))?;
+            Ok(())
+        })?;
+        xml.create_element("NtryDtls").write_inner_content(|xml| {
+            xml.create_element("TxDtls").write_inner_content(|xml| {
+                xml.create_element("Refs")
+                    .write_inner_content(|xml| text(xml, "EndToEndId", &reference))?;
+                Ok(())
+            })?;
+            Ok(())
+        })?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// The amount without its sign, followed by whether it is a credit or a debit
+fn amount<W: io::Write>(
+    xml: &mut Writer<W>,
+    amount: FixedPoint,
+    options: &StatementOptions,
+) -> io::Result<()> {
+    let Precision { decimals, rounding } = options.precision;
+    let rounded = amount
+        .abs()
+        .round_to(decimals, rounding)
+        .unwrap_or(amount.abs());
+    xml.create_element("Amt")
+        .with_attribute(("Ccy", options.currency.as_str()))
+        .write_text_content(BytesText::new(
+            &rounded.formatted().decimals(decimals).to_string(),
+        ))?;
+    let indicator = if amount < FixedPoint::from_f64(0.0) {
+        "DBIT"
+    } else {
+        "CRDT"
+    };
+    text(xml, "CdtDbtInd", indicator)
+}
+
+/// An element holding only text
+fn text<W: io::Write>(xml: &mut Writer<W>, name: &str, value: &str) -> io::Result<()> {
+    xml.create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn camt053_statement() {
+        let xml = std::fs::read_to_string("testdata/camt053.xml").unwrap();
+        let entries = parse(&xml).unwrap();
+
+        assert_eq!(4, entries.len());
+        assert!(entries
+            .iter()
+            .all(|entry| entry.account == "NL91ABNA0417164300"));
+
+        assert!(entries[0].credit);
+        assert!(!entries[0].reversal);
+        assert_eq!("E2E-0001", entries[0].reference);
+        assert_eq!("150.25".parse(), Ok(entries[0].amount));
+        assert_eq!(
+            parse_datetime("2023-01-05T09:30:00+01:00"),
+            entries[0].booked
+        );
+
+        // no end-to-end id given, the servicer reference is used instead
+        assert!(!entries[1].credit);
+        assert_eq!("SVC-0002", entries[1].reference);
+
+        // returned payment of the first credit
+        assert!(!entries[2].credit);
+        assert!(entries[2].reversal);
+        assert_eq!("E2E-0001", entries[2].reference);
+
+        // reversal of the debit, given by the indicator
+        assert!(entries[3].credit);
+        assert!(entries[3].reversal);
+        assert_eq!(parse_datetime("2023-01-07"), entries[3].booked);
+    }
+
+    #[tokio::test]
+    async fn camt054_notification() {
+        let xml = std::fs::read_to_string("testdata/camt054.xml").unwrap();
+        let entries = parse(&xml).unwrap();
+
+        assert_eq!(1, entries.len());
+        assert_eq!("DE89370400440532013000", entries[0].account);
+        assert_eq!("E2E-R&D-7", entries[0].reference);
+    }
+
+    #[tokio::test]
+    async fn camt_booked_entries_only() {
+        let xml = "<Document><BkToCstmrStmt><Stmt><Acct><Id><IBAN>X</IBAN></Id></Acct>\
+             <Ntry><Amt>1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts><Cd>PDNG</Cd></Sts>\
+             <AcctSvcrRef>SVC-1</AcctSvcrRef></Ntry>\
+             <Ntry><Amt>2.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>INFO</Sts>\
+             <AcctSvcrRef>SVC-2</AcctSvcrRef></Ntry>\
+             <Ntry><Amt>3.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>\
+             <AcctSvcrRef>SVC-3</AcctSvcrRef><NtryDtls><TxDtls><Refs>\
+             <EndToEndId>NOTPROVIDED</EndToEndId></Refs></TxDtls></NtryDtls></Ntry>\
+             <Ntry><Amt>4.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>\
+             <AcctSvcrRef>SVC-4</AcctSvcrRef><NtryDtls>\
+             <TxDtls><Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs></TxDtls>\
+             <TxDtls><Refs><EndToEndId>E2E-4</EndToEndId></Refs></TxDtls>\
+             </NtryDtls></Ntry></Stmt></BkToCstmrStmt></Document>";
+        let entries = parse(xml).unwrap();
+
+        // entries without an end-to-end id each keep a reference of their own
+        let references: Vec<&str> = entries.iter().map(|e| e.reference.as_str()).collect();
+        assert_eq!(vec!["SVC-3", "E2E-4"], references);
+    }
+
+    #[tokio::test]
+    async fn camt_errors() {
+        let entry = |inner: &str| {
+            format!(
+                "<Document><BkToCstmrStmt><Stmt><Acct><Id><IBAN>X</IBAN></Id></Acct>\
+                 <Ntry><Sts><Cd>BOOK</Cd></Sts>{}</Ntry></Stmt></BkToCstmrStmt></Document>",
+                inner
+            )
+        };
+        let error = |xml: &str| parse(xml).unwrap_err().to_string();
+
+        assert_eq!(
+            "entry 1: missing <CdtDbtInd>",
+            error(&entry("<Amt>1.00</Amt><NtryRef>1</NtryRef>"))
+        );
+        assert_eq!(
+            "entry 1: invalid <Amt>: 1,00",
+            error(&entry("<Amt>1,00</Amt><CdtDbtInd>CRDT</CdtDbtInd>"))
+        );
+        assert_eq!(
+            "entry 1: invalid <CdtDbtInd>: CREDIT",
+            error(&entry("<Amt>1.00</Amt><CdtDbtInd>CREDIT</CdtDbtInd>"))
+        );
+        assert_eq!(
+            "entry 1: missing <EndToEndId>",
+            error(&entry("<Amt>1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd>"))
+        );
+        assert!(error("<Document><Stmt></Document>").starts_with("invalid xml"));
+        assert_eq!(
+            "entry 1: missing <Sts>",
+            error("<Document><Stmt><Ntry><Amt>1.00</Amt></Ntry></Stmt></Document>")
+        );
+    }
+
+    #[tokio::test]
+    async fn camt053_export_round_trip() {
+        use crate::{accounts::AccountStorage, input::Input, Engine};
+        use std::sync::{Arc, Mutex};
+
+        let mut accounts = AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 2, 1, Some(100.0)));
+        let history = Arc::new(Mutex::new(History::new(&accounts).unwrap()));
+        let mut engine = Engine::with_storage(accounts);
+        engine.add_observer(history.clone());
+        engine.apply_iter(vec![
+            Input::new(TransactionType::Deposit, 1, 2, Some(10.5))
+                .with_timestamp(Timestamp::from_millis(1672876800000)),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(2.5)),
+            Input::new(TransactionType::Deposit, 1, 5, Some(5.0)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+            Input::new(TransactionType::Chargeback, 1, 2, None),
+            Input::new(TransactionType::Withdrawal, 2, 4, Some(30.0)),
+            Input::new(TransactionType::Deposit, 3, 6, Some(1.0)),
+            Input::new(TransactionType::Dispute, 3, 6, None),
+        ]);
+
+        let options = StatementOptions {
+            currency: "EUR".to_string(),
+            precision: Precision::default(),
+            created: Timestamp::from_millis(1672963200000),
+        };
+        let mut out = Vec::new();
+        write_statements(&history.lock().unwrap(), &options, &mut out).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+
+        // the funds of the open dispute are held, and count towards the booked balance
+        let compact: String = xml.lines().map(str::trim).collect();
+        let closing = |code: &str, amount: &str| {
+            format!(
+                "{}</CdOrPrtry></Tp><Amt Ccy=\"EUR\">{}</Amt><CdtDbtInd>CRDT</CdtDbtInd>",
+                code, amount
+            )
+        };
+        assert!(compact.contains(&closing("<Cd>CLBD</Cd>", "2.5000")));
+        assert!(compact.contains(&closing("<Cd>CLBD</Cd>", "1.0000")));
+        assert!(compact.contains(&closing("<Cd>CLAV</Cd>", "0.0000")));
+        assert!(compact.contains(&closing("<Prtry>HELD</Prtry>", "1.0000")));
+        assert!(compact.contains(&closing("<Cd>OPBD</Cd>", "100.0000")));
+
+        // the disputes only move funds to held, they are not booked
+        let entries = parse(&xml).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let amount = entry.amount.to_string();
+                (
+                    entry.account.as_str(),
+                    entry.reference.as_str(),
+                    entry.credit,
+                    entry.reversal,
+                    amount,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("1", "2", true, false, "10.5000".to_string()),
+                ("1", "3", false, false, "2.5000".to_string()),
+                ("1", "5", true, false, "5.0000".to_string()),
+                ("1", "2", false, true, "10.5000".to_string()),
+                ("2", "4", false, false, "30.0000".to_string()),
+                ("3", "6", true, false, "1.0000".to_string()),
+            ],
+            summary
+        );
+        assert_eq!(parse_datetime("2023-01-05"), entries[0].booked);
+    }
+}
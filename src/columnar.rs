@@ -0,0 +1,378 @@
+use std::{fmt::Display, fs::File, io, path::Path, str::FromStr, sync::Arc};
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, TimestampMillisecondArray,
+    UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use parquet::errors::ParquetError;
+
+use crate::{
+    accounts::{AccountStorage, StoreError},
+    history::{Booking, History, Rejected},
+    id::ClientId,
+    simple_fp::DECIMALS,
+    timestamp::Timestamp,
+    FixedPoint,
+};
+
+/// The columnar formats the results can be written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    /// Arrow IPC files
+    Arrow,
+    /// Parquet files, compressed with snappy
+    Parquet,
+}
+
+impl ColumnarFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ColumnarFormat::Arrow => "arrow",
+            ColumnarFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ColumnarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrow" | "ipc" => Ok(ColumnarFormat::Arrow),
+            "parquet" => Ok(ColumnarFormat::Parquet),
+            other => Err(format!("unknown columnar format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ColumnarError {
+    /// A file could not be created or written
+    Io(io::Error),
+    /// The columns could not be put together or written as Arrow IPC
+    Arrow(ArrowError),
+    /// The columns could not be written as Parquet
+    Parquet(ParquetError),
+    /// The accounts could not be read from their store
+    Store(StoreError),
+}
+
+impl Display for ColumnarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnarError::Io(e) => write!(f, "{}", e),
+            ColumnarError::Arrow(e) => write!(f, "{}", e),
+            ColumnarError::Parquet(e) => write!(f, "{}", e),
+            ColumnarError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ColumnarError {}
+
+impl From<StoreError> for ColumnarError {
+    fn from(e: StoreError) -> Self {
+        ColumnarError::Store(e)
+    }
+}
+
+impl From<io::Error> for ColumnarError {
+    fn from(e: io::Error) -> Self {
+        ColumnarError::Io(e)
+    }
+}
+
+impl From<ArrowError> for ColumnarError {
+    fn from(e: ArrowError) -> Self {
+        ColumnarError::Arrow(e)
+    }
+}
+
+impl From<ParquetError> for ColumnarError {
+    fn from(e: ParquetError) -> Self {
+        ColumnarError::Parquet(e)
+    }
+}
+
+/// Amounts keep the scale of [`FixedPoint`], so they are exact
+fn decimal(values: impl Iterator<Item = Option<FixedPoint>>) -> Result<ArrayRef, ArrowError> {
+    let array = values
+        .map(|value| value.map(FixedPoint::to_raw))
+        .collect::<Decimal128Array>()
+        .with_precision_and_scale(38, DECIMALS as i8)?;
+    Ok(Arc::new(array))
+}
+
+fn decimal_field(name: &str, nullable: bool) -> Field {
+    Field::new(name, DataType::Decimal128(38, DECIMALS as i8), nullable)
+}
+
+fn timestamps(values: impl Iterator<Item = Option<Timestamp>>) -> ArrayRef {
+    let array = values
+        .map(|timestamp| timestamp.map(Timestamp::millis))
+        .collect::<TimestampMillisecondArray>()
+        .with_timezone("UTC");
+    Arc::new(array)
+}
+
+fn timestamp_field() -> Field {
+    Field::new(
+        "timestamp",
+        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        true,
+    )
+}
+
+fn strings(values: impl Iterator<Item = String>) -> ArrayRef {
+    Arc::new(values.map(Some).collect::<StringArray>())
+}
+
+fn clients(values: impl Iterator<Item = ClientId>) -> ArrayRef {
+    Arc::new(values.map(|client| client.0).collect::<UInt64Array>())
+}
+
+fn balances(accounts: &AccountStorage) -> Result<RecordBatch, ColumnarError> {
+    let mut ids = Vec::new();
+    let mut balances = Vec::new();
+    accounts.accounts().for_each(&mut |client, account| {
+        ids.push(client);
+        balances.push((
+            account.available(),
+            account.held(),
+            account.total(),
+            account.locked(),
+            account.credit_used(),
+        ));
+    })?;
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt64, false),
+        decimal_field("available", false),
+        decimal_field("held", false),
+        decimal_field("total", false),
+        Field::new("locked", DataType::Boolean, false),
+        decimal_field("credit_used", false),
+    ]);
+    let columns = vec![
+        clients(ids.into_iter()),
+        decimal(balances.iter().map(|balance| Some(balance.0)))?,
+        decimal(balances.iter().map(|balance| Some(balance.1)))?,
+        decimal(balances.iter().map(|balance| Some(balance.2)))?,
+        Arc::new(
+            balances
+                .iter()
+                .map(|balance| Some(balance.3))
+                .collect::<BooleanArray>(),
+        ),
+        decimal(balances.iter().map(|balance| Some(balance.4)))?,
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Every movement of funds in the history, in the order they were applied
+fn journal(history: &History) -> Result<RecordBatch, ArrowError> {
+    let mut rows: Vec<(ClientId, Booking)> = history
+        .clients()
+        .flat_map(|client| {
+            history
+                .bookings(client)
+                .map(move |booking| (client, booking))
+        })
+        .collect();
+    rows.sort_unstable_by_key(|(_, row)| row.seq);
+    let schema = Schema::new(vec![
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("client", DataType::UInt64, false),
+        Field::new("tx", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("debit", DataType::Utf8, false),
+        Field::new("credit", DataType::Utf8, false),
+        decimal_field("amount", false),
+        timestamp_field(),
+    ]);
+    let columns = vec![
+        Arc::new(rows.iter().map(|(_, row)| row.seq).collect::<UInt64Array>()) as ArrayRef,
+        clients(rows.iter().map(|(client, _)| *client)),
+        strings(rows.iter().map(|(_, row)| row.tx.to_string())),
+        strings(rows.iter().map(|(_, row)| row.r#type.to_string())),
+        strings(rows.iter().map(|(_, row)| row.movement.debit.to_string())),
+        strings(rows.iter().map(|(_, row)| row.movement.credit.to_string())),
+        decimal(rows.iter().map(|(_, row)| Some(row.movement.amount)))?,
+        timestamps(rows.iter().map(|(_, row)| row.timestamp)),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+fn rejections(rows: &[Rejected]) -> Result<RecordBatch, ArrowError> {
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt64, false),
+        Field::new("tx", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        decimal_field("amount", true),
+        timestamp_field(),
+        Field::new("reason", DataType::Utf8, false),
+    ]);
+    let columns = vec![
+        clients(rows.iter().map(|row| row.client)),
+        strings(rows.iter().map(|row| row.tx.to_string())),
+        strings(rows.iter().map(|row| row.r#type.to_string())),
+        decimal(rows.iter().map(|row| row.amount))?,
+        timestamps(rows.iter().map(|row| row.timestamp)),
+        strings(rows.iter().map(|row| row.reason.to_string())),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+fn write_batch(
+    path: &Path,
+    format: ColumnarFormat,
+    batch: &RecordBatch,
+) -> Result<(), ColumnarError> {
+    let file = File::create(path)?;
+    match format {
+        ColumnarFormat::Arrow => {
+            let mut writer = arrow_ipc::writer::FileWriter::try_new(file, &batch.schema())?;
+            writer.write(batch)?;
+            writer.finish()?;
+        }
+        ColumnarFormat::Parquet => {
+            let properties = parquet::file::properties::WriterProperties::builder()
+                .set_compression(parquet::basic::Compression::SNAPPY)
+                .build();
+            let mut writer =
+                parquet::arrow::ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+            writer.write(batch)?;
+            writer.close()?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the balances of every account, the journal of every movement of funds and the rejected
+/// transactions of the history as `balances`, `journal` and `rejections` files in the directory,
+/// which is created if needed. Amounts are decimals with the 4 decimals of [`FixedPoint`]
+pub fn write_tables(
+    dir: &str,
+    format: ColumnarFormat,
+    accounts: &AccountStorage,
+    history: &History,
+) -> Result<(), ColumnarError> {
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir)?;
+    let files = [
+        ("balances", balances(accounts)?),
+        ("journal", journal(history)?),
+        ("rejections", rejections(history.rejected())?),
+    ];
+    for (name, batch) in files {
+        let path = dir.join(format!("{}.{}", name, format.extension()));
+        write_batch(&path, format, &batch)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::{Input, TransactionType},
+        Engine,
+    };
+    use arrow_array::Array;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn columnar_round_trip() {
+        let history = Arc::new(Mutex::new(History::default()));
+        let mut engine = Engine::new("");
+        engine.add_observer(history.clone());
+        engine.apply_iter(vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(10.1234))
+                .with_timestamp(Timestamp::from_millis(1672876800000)),
+            Input::new(TransactionType::Withdrawal, 1, 2, Some(50.0)),
+            Input::new(TransactionType::Deposit, 2, 3, Some(2.5)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+        ]);
+        history.lock().unwrap().reject(
+            &Input::new(TransactionType::Deposit, 2, 4, Some(-1.0)),
+            "invalid",
+        );
+
+        for format in [ColumnarFormat::Arrow, ColumnarFormat::Parquet] {
+            let dir = std::env::temp_dir().join(format!("toypa_columnar_{}", format.extension()));
+            let dir = dir.to_str().unwrap();
+            write_tables(dir, format, engine.storage(), &history.lock().unwrap()).unwrap();
+
+            let read = |name: &str| -> RecordBatch {
+                let path = Path::new(dir).join(format!("{}.{}", name, format.extension()));
+                let file = File::open(path).unwrap();
+                match format {
+                    ColumnarFormat::Arrow => arrow_ipc::reader::FileReader::try_new(file, None)
+                        .unwrap()
+                        .next()
+                        .unwrap()
+                        .unwrap(),
+                    ColumnarFormat::Parquet => {
+                        parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file)
+                            .unwrap()
+                            .build()
+                            .unwrap()
+                            .next()
+                            .unwrap()
+                            .unwrap()
+                    }
+                }
+            };
+
+            let balances = read("balances");
+            assert_eq!(2, balances.num_rows());
+            let held = balances
+                .column_by_name("held")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Decimal128Array>()
+                .unwrap();
+            assert_eq!(&DataType::Decimal128(38, 4), held.data_type());
+            assert_eq!("10.1234", held.value_as_string(0));
+            assert_eq!("0.0000", held.value_as_string(1));
+
+            let journal = read("journal");
+            assert_eq!(3, journal.num_rows());
+            let debit = journal
+                .column_by_name("debit")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            assert_eq!("held", debit.value(2));
+            let client = journal
+                .column_by_name("client")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap();
+            assert_eq!(vec![1, 2, 1], client.values().to_vec());
+            let seq = journal
+                .column_by_name("seq")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap();
+            assert_eq!(vec![1, 2, 3], seq.values().to_vec());
+            let timestamp = journal.column_by_name("timestamp").unwrap();
+            assert!(!timestamp.is_null(0));
+            assert!(timestamp.is_null(1));
+
+            let rejections = read("rejections");
+            assert_eq!(2, rejections.num_rows());
+            let reason = rejections
+                .column_by_name("reason")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            assert_eq!("not_enough_available_funds", reason.value(0));
+            assert_eq!("invalid", reason.value(1));
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fs::File, io, path::Path, pin::Pin};
+
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, ZstdDecoder};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The compression formats files can be read and written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// The compression of a file going by its extension, `.gz`, `.zst` or `.bz2`
+    pub fn from_extension(pathname: &str) -> Self {
+        match Path::new(pathname).extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            Some("bz2") => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+
+    /// The compression of a file going by the magic bytes it starts with
+    pub fn from_magic(head: &[u8]) -> Self {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if head.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// A file being read, decompressed on the fly if needed
+pub type Reader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Opens a file for reading, decompressing it while it is read if it is compressed. The magic
+/// bytes decide, the extension is only used when they are not recognized
+pub async fn open(pathname: &str) -> io::Result<Reader> {
+    let mut file = BufReader::new(tokio::fs::File::open(pathname).await?);
+    // peeking does not consume anything, so the decoder still sees the magic bytes
+    let compression = match Compression::from_magic(file.fill_buf().await?) {
+        Compression::None => Compression::from_extension(pathname),
+        compression => compression,
+    };
+    // A file may hold several compressed streams one after the other, like files joined with
+    // `cat` or written by parallel compressors, which all have to be read
+    let reader: Reader = match compression {
+        Compression::None => Box::pin(file),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::pin(decoder)
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::pin(decoder)
+        }
+        Compression::Bzip2 => {
+            let mut decoder = BzDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::pin(decoder)
+        }
+    };
+    Ok(reader)
+}
+
+/// A file being written, compressed on the fly if needed. [`Writer::finish`] has to be called
+/// once everything is written, to end the compressed stream and find out whether that worked
+pub struct Writer {
+    encoder: Encoder,
+}
+
+enum Encoder {
+    None(io::BufWriter<File>),
+    Gzip(flate2::write::GzEncoder<io::BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, io::BufWriter<File>>),
+    Bzip2(bzip2::write::BzEncoder<io::BufWriter<File>>),
+}
+
+impl Writer {
+    /// Ends the compressed stream and flushes the file
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self.encoder {
+            Encoder::None(file) => file,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+            Encoder::Bzip2(encoder) => encoder.finish()?,
+        };
+        io::Write::flush(&mut file)
+    }
+
+    fn inner(&mut self) -> &mut dyn io::Write {
+        match &mut self.encoder {
+            Encoder::None(file) => file,
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+            Encoder::Bzip2(encoder) => encoder,
+        }
+    }
+}
+
+impl io::Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/// Creates a file for writing, compressed as its extension says
+pub fn create(pathname: &str) -> io::Result<Writer> {
+    let file = io::BufWriter::new(File::create(pathname)?);
+    let encoder = match Compression::from_extension(pathname) {
+        Compression::None => Encoder::None(file),
+        Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        )),
+        Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+        Compression::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(
+            file,
+            bzip2::Compression::default(),
+        )),
+    };
+    Ok(Writer { encoder })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn compression_round_trip() {
+        let content = std::fs::read_to_string("testdata/input.csv").unwrap();
+        for extension in ["csv", "csv.gz", "csv.zst", "csv.bz2"] {
+            let path = std::env::temp_dir().join(format!("toypa_compression.{}", extension));
+            let path = path.to_str().unwrap();
+            let mut writer = create(path).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+            writer.finish().unwrap();
+
+            let mut read = String::new();
+            open(path)
+                .await
+                .unwrap()
+                .read_to_string(&mut read)
+                .await
+                .unwrap();
+            assert_eq!(content, read, "{}", extension);
+
+            // the magic bytes are enough without the extension
+            let renamed = format!("{}.data", path);
+            std::fs::rename(path, &renamed).unwrap();
+            let mut read = String::new();
+            open(&renamed)
+                .await
+                .unwrap()
+                .read_to_string(&mut read)
+                .await
+                .unwrap();
+            assert_eq!(content, read, "{}", extension);
+            std::fs::remove_file(&renamed).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn compression_reads_every_stream() {
+        for extension in ["gz", "zst", "bz2"] {
+            let path = std::env::temp_dir().join(format!("toypa_streams.csv.{}", extension));
+            let path = path.to_str().unwrap();
+            let mut joined = Vec::new();
+            for part in ["type,client,tx,amount\n", "deposit,1,1,1.0\n"] {
+                let mut writer = create(path).unwrap();
+                writer.write_all(part.as_bytes()).unwrap();
+                writer.finish().unwrap();
+                joined.extend(std::fs::read(path).unwrap());
+            }
+            std::fs::write(path, joined).unwrap();
+
+            let mut read = String::new();
+            open(path)
+                .await
+                .unwrap()
+                .read_to_string(&mut read)
+                .await
+                .unwrap();
+            assert_eq!(
+                "type,client,tx,amount\ndeposit,1,1,1.0\n", read,
+                "{}",
+                extension
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
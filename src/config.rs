@@ -0,0 +1,431 @@
+use std::{collections::BTreeMap, fmt::Display, fs, io, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    id::ClientId,
+    input::{self, Columns, InputOptions, TransactionType},
+    output::{Format, Precision},
+    policy::{self, LockedAccounts, Policy, WithDisputeWindow, WithLockedAccounts},
+    simple_fp::DECIMALS,
+    timestamp::MILLIS_PER_DAY,
+    FixedPoint, Rounding,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io(io::Error),
+    /// The file is not valid toml, or has keys or values that are not known
+    Parse(toml::de::Error),
+    /// A value is of the right type, but not allowed, the string says which and why
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "invalid config file: {}", e),
+            ConfigError::Invalid(message) => write!(f, "invalid config file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+/// The config file as written, before any of the values are checked.
+/// Amounts are strings so they are read exactly, without going through a float
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawConfig {
+    precision: Option<usize>,
+    rounding: Option<Rounding>,
+    policy: Option<String>,
+    locked_accounts: Option<LockedAccounts>,
+    dispute_window_days: Option<i64>,
+    #[serde(default)]
+    limits: BTreeMap<String, String>,
+    #[serde(default)]
+    input: RawInput,
+    #[serde(default)]
+    output: RawOutput,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawInput {
+    format: Option<String>,
+    delimiter: Option<String>,
+    trim: Option<bool>,
+    quote: Option<String>,
+    quoting: Option<bool>,
+    headers: Option<bool>,
+    columns: Option<RawColumns>,
+    order: Option<Vec<String>>,
+    #[serde(default)]
+    types: BTreeMap<String, TransactionType>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawColumns {
+    r#type: Option<String>,
+    client: Option<String>,
+    tx: Option<String>,
+    amount: Option<String>,
+    timestamp: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawOutput {
+    format: Option<String>,
+    path: Option<String>,
+}
+
+/// Every setting of the engine, as read from a toml config file. Anything left out of the file
+/// keeps its default, and command line options given on top of it take precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// How amounts are rounded in the output
+    pub precision: Precision,
+    /// Name of the dispute policy to follow
+    pub policy: String,
+    /// What locked accounts still accept, None leaves it to the policy
+    pub locked_accounts: Option<LockedAccounts>,
+    /// Reject disputes on transactions older than this, in milliseconds
+    pub dispute_window: Option<i64>,
+    /// Credit limit of each client
+    pub limits: BTreeMap<ClientId, FixedPoint>,
+    pub input: InputOptions,
+    /// Format of the account balances, None leaves it to the command line
+    pub format: Option<Format>,
+    /// File to write the account balances to
+    pub output: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            precision: Precision::default(),
+            policy: "default".to_string(),
+            locked_accounts: None,
+            dispute_window: None,
+            limits: BTreeMap::new(),
+            input: InputOptions::default(),
+            format: None,
+            output: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads and checks the config file
+    pub fn load(pathname: &str) -> Result<Self, ConfigError> {
+        fs::read_to_string(pathname)?.parse()
+    }
+
+    /// The policy to follow, with the rules for locked accounts applied on top of it
+    pub fn policy(&self) -> Box<dyn Policy> {
+        // the name is checked when the config is read
+        let policy = policy::by_name(&self.policy).unwrap();
+        let policy = match self.locked_accounts {
+            Some(locked) => Box::new(WithLockedAccounts { policy, locked }),
+            None => policy,
+        };
+        match self.dispute_window {
+            Some(dispute_window) => Box::new(WithDisputeWindow {
+                policy,
+                dispute_window,
+            }),
+            None => policy,
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: RawConfig = toml::from_str(s)?;
+        let mut config = Config::default();
+
+        if let Some(decimals) = raw.precision {
+            if decimals > DECIMALS {
+                return Err(invalid(format!(
+                    "precision must be at most {}, got {}",
+                    DECIMALS, decimals
+                )));
+            }
+            config.precision.decimals = decimals;
+        }
+        if let Some(rounding) = raw.rounding {
+            config.precision.rounding = rounding;
+        }
+
+        if let Some(name) = raw.policy {
+            if policy::by_name(&name).is_none() {
+                return Err(invalid(format!(
+                    "unknown policy `{}`, expected one of {}",
+                    name,
+                    policy::NAMES.join(", ")
+                )));
+            }
+            config.policy = name;
+        }
+        config.locked_accounts = raw.locked_accounts;
+        if let Some(days) = raw.dispute_window_days {
+            if days < 0 {
+                return Err(invalid(format!(
+                    "dispute-window-days can not be negative, got {}",
+                    days
+                )));
+            }
+            let window = days.checked_mul(MILLIS_PER_DAY).ok_or_else(|| {
+                invalid(format!("dispute-window-days is too large, got {}", days))
+            })?;
+            config.dispute_window = Some(window);
+        }
+
+        for (client, limit) in raw.limits {
+            let key = format!("limits.{}", client);
+            let client = client
+                .parse()
+                .map_err(|_| invalid(format!("{} is not a client id", key)))?;
+            config.limits.insert(client, amount(&key, &limit)?);
+        }
+
+        if let Some(format) = raw.input.format {
+            config.input.format = format
+                .parse()
+                .map_err(|e| invalid(format!("input.format: {}", e)))?;
+        }
+        if let Some(delimiter) = raw.input.delimiter {
+            config.input.delimiter = character("input.delimiter", &delimiter)?;
+        }
+        if let Some(quote) = raw.input.quote {
+            config.input.quote = character("input.quote", &quote)?;
+        }
+        if let Some(quoting) = raw.input.quoting {
+            config.input.quoting = quoting;
+        }
+        if let Some(trim) = raw.input.trim {
+            config.input.trim = trim;
+        }
+        if let Some(headers) = raw.input.headers {
+            config.input.has_headers = headers;
+        }
+        if let Some(order) = raw.input.order {
+            config.input.order = check_order(order)?;
+        }
+        config.input.types = raw.input.types;
+        if let Some(columns) = raw.input.columns {
+            config.input.columns = columns.into_columns();
+        }
+
+        if let Some(format) = raw.output.format {
+            let format = format
+                .parse()
+                .map_err(|e| invalid(format!("output.format: {}", e)))?;
+            config.format = Some(format);
+        }
+        config.output = raw.output.path;
+
+        Ok(config)
+    }
+}
+
+impl RawColumns {
+    fn into_columns(self) -> Columns {
+        let defaults = Columns::default();
+        Columns {
+            r#type: self.r#type.unwrap_or(defaults.r#type),
+            client: self.client.unwrap_or(defaults.client),
+            tx: self.tx.unwrap_or(defaults.tx),
+            amount: self.amount.unwrap_or(defaults.amount),
+            timestamp: self.timestamp.unwrap_or(defaults.timestamp),
+        }
+    }
+}
+
+/// A single byte, like the delimiter of the input
+fn character(key: &str, value: &str) -> Result<u8, ConfigError> {
+    match value.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(invalid(format!(
+            "{} must be a single character, got `{}`",
+            key, value
+        ))),
+    }
+}
+
+/// The fields of a file without a header, every field at most once, and every field an input
+/// can not do without
+fn check_order(order: Vec<String>) -> Result<Vec<String>, ConfigError> {
+    for (i, field) in order.iter().enumerate() {
+        if !input::FIELDS.contains(&field.as_str()) {
+            return Err(invalid(format!(
+                "input.order has unknown field `{}`, expected one of {}",
+                field,
+                input::FIELDS.join(", ")
+            )));
+        }
+        if order[..i].contains(field) {
+            return Err(invalid(format!("input.order has `{}` twice", field)));
+        }
+    }
+    for required in ["type", "client", "tx"] {
+        if !order.iter().any(|field| field == required) {
+            return Err(invalid(format!("input.order is missing `{}`", required)));
+        }
+    }
+    Ok(order)
+}
+
+/// Parses an amount that can not be negative, naming the key it was read from on errors
+fn amount(key: &str, value: &str) -> Result<FixedPoint, ConfigError> {
+    let amount: FixedPoint = value
+        .parse()
+        .map_err(|e| invalid(format!("{} is not a valid amount `{}`: {}", key, value, e)))?;
+    if amount < FixedPoint::from_f64(0.0) {
+        return Err(invalid(format!(
+            "{} can not be negative, got {}",
+            key, value
+        )));
+    }
+    Ok(amount)
+}
+
+fn invalid(message: String) -> ConfigError {
+    ConfigError::Invalid(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputFormat;
+
+    #[tokio::test]
+    async fn config_full() {
+        let config = Config::load("testdata/config.toml").unwrap();
+        let fp = |s: &str| s.parse::<FixedPoint>().unwrap();
+
+        assert_eq!(2, config.precision.decimals);
+        assert_eq!(Rounding::HalfUp, config.precision.rounding);
+        assert_eq!("card-scheme", config.policy);
+        assert_eq!(Some(LockedAccounts::Reject), config.locked_accounts);
+        assert_eq!(Some(90 * MILLIS_PER_DAY), config.dispute_window);
+        assert_eq!(fp("2500.5"), config.limits[&ClientId(3)]);
+        assert_eq!(b';', config.input.delimiter);
+        assert_eq!("kind", config.input.columns.r#type);
+        assert_eq!("client", config.input.columns.client);
+        assert_eq!(Some(Format::JsonLines), config.format);
+        assert_eq!(None, config.output);
+
+        // the rule for locked accounts and the dispute window replace those of the card scheme
+        let policy = config.policy();
+        assert!(!policy.allowed_when_locked(crate::TransactionType::Resolve));
+        assert_eq!(Some(90 * MILLIS_PER_DAY), policy.dispute_window());
+    }
+
+    #[tokio::test]
+    async fn config_input_dialect() {
+        let config: Config = r#"
+            [input]
+            format = "csv"
+            delimiter = "\t"
+            quote = "'"
+            headers = false
+            order = ["tx", "client", "amount", "type"]
+
+            [input.types]
+            DEP = "deposit"
+            WD = "withdrawal"
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(InputFormat::Csv, config.input.format);
+        assert_eq!(b'\t', config.input.delimiter);
+        assert_eq!(b'\'', config.input.quote);
+        assert!(!config.input.has_headers);
+        assert_eq!(vec!["tx", "client", "amount", "type"], config.input.order);
+        assert_eq!(TransactionType::Withdrawal, config.input.types["WD"]);
+    }
+
+    #[tokio::test]
+    async fn config_empty() {
+        assert_eq!(Config::default(), "".parse::<Config>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn config_errors() {
+        let error = |s: &str| s.parse::<Config>().unwrap_err().to_string();
+
+        let unknown = error("precision = 2\nrounding = \"half-even\"\ncolour = \"red\"\n");
+        assert!(unknown.contains("unknown field `colour`"), "{}", unknown);
+        assert!(unknown.contains("line 3"), "{}", unknown);
+        let unknown = error("[output]\nformat = \"csv\"\ncolumns = 2\n");
+        assert!(unknown.contains("unknown field `columns`"), "{}", unknown);
+        let unknown = error("rounding = \"up\"\n");
+        assert!(unknown.contains("unknown variant `up`"), "{}", unknown);
+
+        assert_eq!(
+            "invalid config file: precision must be at most 4, got 6",
+            error("precision = 6")
+        );
+        assert_eq!(
+            "invalid config file: unknown policy `loose`, expected one of default, lenient, strict, card-scheme",
+            error("policy = \"loose\"")
+        );
+        assert_eq!(
+            "invalid config file: dispute-window-days is too large, got 9223372036854775807",
+            error("dispute-window-days = 9223372036854775807")
+        );
+        assert_eq!(
+            "invalid config file: limits.1 can not be negative, got -5",
+            error("[limits]\n1 = \"-5\"")
+        );
+        assert_eq!(
+            "invalid config file: limits.one is not a client id",
+            error("[limits]\none = \"5\"")
+        );
+        assert!(error("[limits]\n1 = \"0.00001\"")
+            .starts_with("invalid config file: limits.1 is not a valid amount `0.00001`"));
+        assert_eq!(
+            "invalid config file: input.delimiter must be a single character, got `;;`",
+            error("[input]\ndelimiter = \";;\"")
+        );
+        assert_eq!(
+            "invalid config file: input.order is missing `tx`",
+            error("[input]\norder = [\"type\", \"client\"]")
+        );
+        assert_eq!(
+            "invalid config file: input.order has `client` twice",
+            error("[input]\norder = [\"type\", \"client\", \"client\", \"tx\"]")
+        );
+        let unknown = error("[input.types]\nDEP = \"deposits\"");
+        assert!(
+            unknown.contains("unknown variant `deposits`"),
+            "{}",
+            unknown
+        );
+        assert_eq!(
+            "invalid config file: output.format: unknown output format: xml",
+            error("[output]\nformat = \"xml\"")
+        );
+    }
+}
@@ -1,36 +1,38 @@
 // Just to create testfiles that one can benchmark against
-mod accounts;
-mod input;
-mod simple_fp;
-
-use input::{Input, TransactionType};
-use simple_fp::FixedPoint;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
+use toypa::{input, Input, TransactionType};
 
 use rand::Rng;
 
 fn main() {
-    let filename = std::env::args()
-        .into_iter()
-        .nth(1)
-        .expect("Expected file name as argument");
+    let mut args = std::env::args().skip(1);
+    let filename = args.next().expect("Expected file name as argument");
+    // the client ids go round after this many clients
+    let clients: u64 = args
+        .next()
+        .map(|n| match n.parse() {
+            Ok(n) if n > 0 => n,
+            _ => panic!("Expected a number of clients above 0"),
+        })
+        .unwrap_or(u16::MAX as u64 + 1);
 
-    let mut i: u32 = 0;
+    let mut i: u64 = 0;
     let mut input = input::Input::new(TransactionType::Deposit, 0, 0, Some(1.0));
 
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(filename)
         .unwrap();
 
     let mut s = String::new();
 
-    let _e = s.push_str(&format!("type, client, tx, amount\n"));
+    s.push_str("type, client, tx, amount\n");
 
-    while i < u16::MAX as u32 * 10 {
-        let _e = s.push_str(&format!(
+    while i < u16::MAX as u64 * 10 {
+        s.push_str(&format!(
             "{}, {}, {}, {:0.4}\n",
             input.r#type(),
             input.client(),
@@ -46,16 +48,11 @@ fn main() {
             TransactionType::Withdrawal
         };
 
-        input = Input::new(
-            txtype,
-            input.client().checked_add(1).map_or(0, |v| v),
-            i,
-            input.amount(),
-        );
+        input = Input::new(txtype, (input.client().0 + 1) % clients, i, input.amount());
 
-        if i % 100 == 0 {
-            let dispute = Input::new(TransactionType::Dispute, input.client(), i, None);
-            let _e = s.push_str(&format!(
+        if i.is_multiple_of(100) {
+            let dispute = Input::new(TransactionType::Dispute, input.client().0, i, None);
+            s.push_str(&format!(
                 "{}, {}, {}, ,\n",
                 dispute.r#type(),
                 dispute.client(),
@@ -66,16 +63,16 @@ fn main() {
             let resolve: f32 = rng.gen_range(0.0..100.0);
 
             if resolve > 10.0 {
-                let dispute = Input::new(TransactionType::Resolve, input.client(), i, None);
-                let _e = s.push_str(&format!(
+                let dispute = Input::new(TransactionType::Resolve, input.client().0, i, None);
+                s.push_str(&format!(
                     "{}, {}, {}, ,\n",
                     dispute.r#type(),
                     dispute.client(),
                     dispute.tx(),
                 ));
             } else {
-                let dispute = Input::new(TransactionType::Chargeback, input.client(), i, None);
-                let _e = s.push_str(&format!(
+                let dispute = Input::new(TransactionType::Chargeback, input.client().0, i, None);
+                s.push_str(&format!(
                     "{}, {}, {}, ,\n",
                     dispute.r#type(),
                     dispute.client(),
@@ -91,7 +88,7 @@ fn main() {
             s = String::new();
         }
     }
-    if s.len() > 0 {
+    if !s.is_empty() {
         let _e = write!(file, "{}", s);
     }
 }
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    accounts::{Account, AccountStorage, DisputeState, StoreError, TransactionError},
+    id::{ClientId, TxId},
+    input::{Input, TransactionType},
+    journal::Movement,
+    observer::Observer,
+};
+
+/// What applying a single transaction did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    /// The funds moved by the transaction, None for transactions that do not move any funds
+    pub movement: Option<Movement>,
+}
+
+/// Counts of what happened when applying many transactions at once
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+impl Summary {
+    fn count(&mut self, res: &Result<Outcome, TransactionError>) {
+        match res {
+            Ok(_) => self.applied += 1,
+            Err(_) => self.rejected += 1,
+        }
+    }
+}
+
+/// The payment engine, owning the accounts and applying transactions to them.
+/// It can be driven one transaction at a time, or from an iterator or stream of transactions
+pub struct Engine<'a> {
+    accounts: AccountStorage<'a>,
+    observers: Vec<Box<dyn Observer + Send + 'a>>,
+}
+
+impl<'a> Engine<'a> {
+    /// Creates an engine with no accounts
+    pub fn new(tx_path: &'a str) -> Self {
+        Self::with_storage(AccountStorage::new(tx_path))
+    }
+
+    /// Creates an engine continuing from existing accounts, for example from a snapshot
+    pub fn with_storage(accounts: AccountStorage<'a>) -> Self {
+        Self {
+            accounts,
+            observers: Vec::new(),
+        }
+    }
+
+    /// Registers an observer that is told about every change the engine makes from now on
+    pub fn add_observer(&mut self, observer: impl Observer + Send + 'a) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Applies a single transaction to the account of its client, creating the account if needed
+    pub fn apply(&mut self, input: Input) -> Result<Outcome, TransactionError> {
+        let client = input.client();
+        let tx = input.tx();
+        let r#type = *input.r#type();
+        if self.observers.is_empty() {
+            let applied = self.accounts.apply(input)?;
+            return Ok(Outcome {
+                client,
+                tx,
+                r#type,
+                movement: applied.movement,
+            });
+        }
+
+        // The observers need to see the input, and whether the account got locked by it
+        let res = self.accounts.apply(input.clone());
+        let locked = res.as_ref().is_ok_and(|applied| applied.locked);
+        let res = res.map(|applied| Outcome {
+            client,
+            tx,
+            r#type,
+            movement: applied.movement,
+        });
+        self.notify(&input, &res, locked);
+        res
+    }
+
+    fn notify(&mut self, input: &Input, res: &Result<Outcome, TransactionError>, locked: bool) {
+        let outcome = match res {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                for observer in &mut self.observers {
+                    observer.on_rejected(input, e);
+                }
+                return;
+            }
+        };
+        let closed = match outcome.r#type {
+            TransactionType::Resolve => Some(DisputeState::Resolved),
+            TransactionType::Chargeback => Some(DisputeState::Reimbursed),
+            _ => None,
+        };
+
+        for observer in &mut self.observers {
+            observer.on_applied(input, outcome);
+            if outcome.r#type == TransactionType::Dispute {
+                observer.on_dispute_opened(outcome.client, outcome.tx);
+            }
+            if let Some(state) = closed {
+                observer.on_dispute_closed(outcome.client, outcome.tx, state);
+            }
+            if locked {
+                observer.on_locked(outcome.client);
+            }
+        }
+    }
+
+    /// Applies every transaction from the iterator in order
+    pub fn apply_iter(&mut self, inputs: impl IntoIterator<Item = Input>) -> Summary {
+        let mut summary = Summary::default();
+        for input in inputs {
+            summary.count(&self.apply(input));
+        }
+        summary
+    }
+
+    /// Applies every transaction from the stream in order
+    pub async fn apply_stream(&mut self, inputs: impl Stream<Item = Input>) -> Summary {
+        let mut summary = Summary::default();
+        tokio::pin!(inputs);
+        while let Some(input) = inputs.next().await {
+            summary.count(&self.apply(input));
+        }
+        summary
+    }
+
+    /// Get the account of a client, if the client has any transactions
+    pub fn account(&self, client: ClientId) -> Result<Option<Account>, StoreError> {
+        self.accounts.account(client)
+    }
+
+    /// Get every account, ordered by client
+    pub fn accounts(&self) -> Result<BTreeMap<ClientId, Account>, StoreError> {
+        let mut accounts = BTreeMap::new();
+        self.accounts.accounts().for_each(&mut |client, account| {
+            accounts.insert(client, account.clone());
+        })?;
+        Ok(accounts)
+    }
+
+    /// Get transaction `tx` of `client`, if it is kept to be disputed
+    pub fn transaction(&self, client: ClientId, tx: TxId) -> Result<Option<Input>, StoreError> {
+        self.accounts.accounts().transaction(client, tx)
+    }
+
+    /// Get the state of the dispute on transaction `tx` of `client`
+    pub fn dispute(&self, client: ClientId, tx: TxId) -> Result<Option<DisputeState>, StoreError> {
+        Ok(self
+            .account(client)?
+            .and_then(|account| account.dispute_state(tx)))
+    }
+
+    /// Get every dispute that has not been resolved or charged back yet, as `(client, tx)`
+    pub fn open_disputes(&self) -> Result<Vec<(ClientId, TxId)>, StoreError> {
+        let mut open = Vec::new();
+        self.accounts.accounts().for_each(&mut |client, account| {
+            let started = account
+                .disputes()
+                .filter(|(_, state)| *state == DisputeState::Started);
+            open.extend(started.map(|(tx, _)| (client, tx)));
+        })?;
+        Ok(open)
+    }
+
+    /// Get a reference to the underlying account storage
+    pub fn storage(&self) -> &AccountStorage<'a> {
+        &self.accounts
+    }
+
+    /// Get a mutable reference to the underlying account storage, to change its settings
+    pub fn storage_mut(&mut self) -> &mut AccountStorage<'a> {
+        &mut self.accounts
+    }
+
+    /// Gives back the account storage
+    pub fn into_storage(self) -> AccountStorage<'a> {
+        self.accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::TransactionType, FixedPoint};
+
+    fn inputs() -> Vec<Input> {
+        vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(10.0)),
+            Input::new(TransactionType::Deposit, 2, 2, Some(5.0)),
+            Input::new(TransactionType::Withdrawal, 2, 3, Some(6.0)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::new(TransactionType::Deposit, 1, 4, Some(2.5)),
+            Input::new(TransactionType::Dispute, 2, 2, None),
+            Input::new(TransactionType::Resolve, 2, 2, None),
+        ]
+    }
+
+    #[tokio::test]
+    async fn engine_apply() {
+        let mut engine = Engine::new("");
+
+        let outcome = engine
+            .apply(Input::new(TransactionType::Deposit, 1, 1, Some(10.0)))
+            .unwrap();
+        assert_eq!(ClientId(1), outcome.client);
+        assert_eq!(TxId::Number(1), outcome.tx);
+        assert_eq!(
+            Some(FixedPoint::from_f64(10.0)),
+            outcome.movement.map(|m| m.amount)
+        );
+
+        let res = engine.apply(Input::new(TransactionType::Withdrawal, 1, 2, Some(11.0)));
+        assert!(matches!(
+            res,
+            Err(TransactionError::NotEnoughAvailableFunds)
+        ));
+
+        let res = engine.apply(Input::new(TransactionType::Dispute, 1, 2, None));
+        assert!(matches!(res, Err(TransactionError::MissingTxId)));
+    }
+
+    #[tokio::test]
+    async fn engine_iter_and_stream_agree() {
+        let mut from_iter = Engine::new("");
+        let summary = from_iter.apply_iter(inputs());
+        assert_eq!(
+            Summary {
+                applied: 6,
+                rejected: 1
+            },
+            summary
+        );
+
+        let mut from_stream = Engine::new("");
+        let summary_stream = from_stream.apply_stream(tokio_stream::iter(inputs())).await;
+        assert_eq!(summary, summary_stream);
+
+        for (client, account) in from_iter.accounts().unwrap() {
+            let other = from_stream.account(client).unwrap().unwrap();
+            assert_eq!(account.available(), other.available());
+            assert_eq!(account.held(), other.held());
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Observer for Recorder {
+        fn on_applied(&mut self, _input: &Input, outcome: &Outcome) {
+            let event = format!("applied {} {}", outcome.r#type, outcome.tx);
+            self.0.lock().unwrap().push(event);
+        }
+        fn on_rejected(&mut self, input: &Input, _error: &TransactionError) {
+            let event = format!("rejected {} {}", input.r#type(), input.tx());
+            self.0.lock().unwrap().push(event);
+        }
+        fn on_locked(&mut self, client: ClientId) {
+            self.0.lock().unwrap().push(format!("locked {}", client));
+        }
+        fn on_dispute_opened(&mut self, client: ClientId, tx: TxId) {
+            let event = format!("opened {} {}", client, tx);
+            self.0.lock().unwrap().push(event);
+        }
+        fn on_dispute_closed(&mut self, client: ClientId, tx: TxId, state: DisputeState) {
+            let event = format!("closed {} {} {:?}", client, tx, state);
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn engine_observers() {
+        let recorder = Recorder::default();
+        let mut engine = Engine::new("");
+        engine.add_observer(recorder.clone());
+
+        engine.apply_iter(inputs());
+        engine.apply_iter(vec![
+            Input::new(TransactionType::Chargeback, 1, 1, None),
+            Input::new(TransactionType::Deposit, 1, 5, Some(1.0)),
+        ]);
+
+        let events = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            vec![
+                "applied deposit 1",
+                "applied deposit 2",
+                "rejected withdrawal 3",
+                "applied dispute 1",
+                "opened 1 1",
+                "applied deposit 4",
+                "applied dispute 2",
+                "opened 2 2",
+                "applied resolve 2",
+                "closed 2 2 Resolved",
+                "applied chargeback 1",
+                "closed 1 1 Reimbursed",
+                "locked 1",
+                "rejected deposit 5",
+            ],
+            events
+        );
+    }
+
+    #[tokio::test]
+    async fn engine_queries() {
+        let mut engine = Engine::new("");
+        engine.apply_iter(inputs());
+
+        assert_eq!(2, engine.accounts().unwrap().len());
+        assert_eq!(10.0, engine.account(ClientId(1)).unwrap().unwrap().held());
+        assert!(engine.account(ClientId(3)).unwrap().is_none());
+
+        assert_eq!(
+            Some(DisputeState::Started),
+            engine.dispute(ClientId(1), TxId::Number(1)).unwrap()
+        );
+        assert_eq!(
+            Some(DisputeState::Resolved),
+            engine.dispute(ClientId(2), TxId::Number(2)).unwrap()
+        );
+        assert_eq!(None, engine.dispute(ClientId(1), TxId::Number(4)).unwrap());
+        assert_eq!(
+            vec![(ClientId(1), TxId::Number(1))],
+            engine.open_disputes().unwrap()
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    accounts::{AccountStorage, DisputeState, StoreError, TransactionError},
+    engine::Outcome,
+    id::{ClientId, TxId},
+    input::{Input, TransactionType},
+    journal::{Ledger, Movement},
+    observer::Observer,
+    timestamp::Timestamp,
+    FixedPoint,
+};
+
+/// The funds of a client at some point
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Balance {
+    pub available: FixedPoint,
+    pub held: FixedPoint,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self {
+            available: FixedPoint::from_f64(0.0),
+            held: FixedPoint::from_f64(0.0),
+        }
+    }
+}
+
+impl Balance {
+    pub fn total(&self) -> FixedPoint {
+        self.available + self.held
+    }
+
+    /// The balance after the movement
+    pub fn after(mut self, movement: &Movement) -> Self {
+        // the debited ledger grows, the credited one shrinks
+        match movement.debit {
+            Ledger::Available => self.available += movement.amount,
+            Ledger::Held => self.held += movement.amount,
+            _ => {}
+        }
+        match movement.credit {
+            Ledger::Available => self.available -= movement.amount,
+            Ledger::Held => self.held -= movement.amount,
+            _ => {}
+        }
+        self
+    }
+}
+
+/// A transaction applied to the account of a client, and what it changed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Applied {
+    /// The position of its movement among the movements of every client, numbered like the seq of
+    /// the journal, none when nothing moved
+    pub seq: Option<u64>,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub timestamp: Option<Timestamp>,
+    pub movement: Option<Movement>,
+    /// The state the dispute on the tx went to, for disputes, resolves and chargebacks
+    pub dispute: Option<DisputeState>,
+    /// True when the transaction locked the account
+    pub locked: bool,
+}
+
+impl Applied {
+    /// The balance after the transaction
+    pub fn after(&self, balance: Balance) -> Balance {
+        self.movement
+            .map_or(balance, |movement| balance.after(&movement))
+    }
+}
+
+/// A movement of funds applied to the account of a client
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Booking {
+    pub seq: u64,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub movement: Movement,
+    pub timestamp: Option<Timestamp>,
+}
+
+impl Booking {
+    /// Whether the booking changes the total funds of the client, which is not the case when funds
+    /// only move between available and held
+    pub fn changes_total(&self) -> bool {
+        let own = |ledger| matches!(ledger, Ledger::Available | Ledger::Held);
+        own(self.movement.debit) != own(self.movement.credit)
+    }
+}
+
+/// A transaction that was not applied, and why
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub amount: Option<FixedPoint>,
+    pub timestamp: Option<Timestamp>,
+    /// A stable code, see [`TransactionError::code`]
+    pub reason: &'static str,
+}
+
+/// Every transaction applied to every client since the history started, in the order they were
+/// applied, and every transaction that was rejected.
+///
+/// It is an observer, shared with the engine as an `Arc<Mutex<History>>` so it can be read once
+/// the engine is done
+#[derive(Debug, Default)]
+pub struct History {
+    opening: BTreeMap<ClientId, Balance>,
+    applied: BTreeMap<ClientId, Vec<Applied>>,
+    rejected: Vec<Rejected>,
+    /// The seq of the last movement
+    seq: u64,
+}
+
+impl History {
+    /// Starts the history from the balances the accounts have now
+    pub fn new(accounts: &AccountStorage) -> Result<Self, StoreError> {
+        let mut opening = BTreeMap::new();
+        accounts.accounts().for_each(&mut |client, account| {
+            let balance = Balance {
+                available: account.available(),
+                held: account.held(),
+            };
+            opening.insert(client, balance);
+        })?;
+        Ok(Self {
+            opening,
+            applied: BTreeMap::new(),
+            rejected: Vec::new(),
+            seq: 0,
+        })
+    }
+
+    /// Numbers the movements on from `seq`, to continue a journal that already has entries
+    pub fn continuing(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// The balance of the client when the history started
+    pub fn opening(&self, client: ClientId) -> Balance {
+        self.opening.get(&client).copied().unwrap_or_default()
+    }
+
+    /// The transactions applied to the client, in the order they were applied
+    pub fn applied(&self, client: ClientId) -> &[Applied] {
+        self.applied.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// The movements of funds of the client
+    pub fn bookings(&self, client: ClientId) -> impl Iterator<Item = Booking> + '_ {
+        self.applied(client).iter().filter_map(|applied| {
+            Some(Booking {
+                seq: applied.seq?,
+                tx: applied.tx,
+                r#type: applied.r#type,
+                movement: applied.movement?,
+                timestamp: applied.timestamp,
+            })
+        })
+    }
+
+    /// Records the input as rejected, for inputs that never reach the engine
+    pub fn reject(&mut self, input: &Input, reason: &'static str) {
+        self.rejected.push(Rejected {
+            client: input.client(),
+            tx: input.tx(),
+            r#type: *input.r#type(),
+            amount: input.amount_as_fp(),
+            timestamp: input.timestamp(),
+            reason,
+        });
+    }
+
+    /// Every rejected transaction, in the order they were rejected
+    pub fn rejected(&self) -> &[Rejected] {
+        &self.rejected
+    }
+
+    fn last_mut(&mut self, client: ClientId) -> Option<&mut Applied> {
+        self.applied.get_mut(&client)?.last_mut()
+    }
+
+    /// Every client with an opening balance or an applied transaction
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        let mut clients: Vec<ClientId> = self
+            .opening
+            .keys()
+            .chain(self.applied.keys())
+            .copied()
+            .collect();
+        clients.sort_unstable();
+        clients.dedup();
+        clients.into_iter()
+    }
+}
+
+impl Observer for Arc<Mutex<History>> {
+    fn on_applied(&mut self, input: &Input, outcome: &Outcome) {
+        let mut history = self.lock().unwrap();
+        let seq = outcome.movement.map(|_| {
+            history.seq += 1;
+            history.seq
+        });
+        history
+            .applied
+            .entry(outcome.client)
+            .or_default()
+            .push(Applied {
+                seq,
+                tx: outcome.tx,
+                r#type: outcome.r#type,
+                timestamp: input.timestamp(),
+                movement: outcome.movement,
+                dispute: None,
+                locked: false,
+            });
+    }
+
+    fn on_rejected(&mut self, input: &Input, error: &TransactionError) {
+        self.lock().unwrap().reject(input, error.code());
+    }
+
+    // the other hooks are called right after the transaction they are about is applied
+
+    fn on_locked(&mut self, client: ClientId) {
+        if let Some(applied) = self.lock().unwrap().last_mut(client) {
+            applied.locked = true;
+        }
+    }
+
+    fn on_dispute_opened(&mut self, client: ClientId, _tx: TxId) {
+        if let Some(applied) = self.lock().unwrap().last_mut(client) {
+            applied.dispute = Some(DisputeState::Started);
+        }
+    }
+
+    fn on_dispute_closed(&mut self, client: ClientId, _tx: TxId, state: DisputeState) {
+        if let Some(applied) = self.lock().unwrap().last_mut(client) {
+            applied.dispute = Some(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    #[tokio::test]
+    async fn history_records_bookings() {
+        let mut accounts = AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 1, Some(10.0)));
+        let history = Arc::new(Mutex::new(History::new(&accounts).unwrap()));
+        let mut engine = Engine::with_storage(accounts);
+        engine.add_observer(history.clone());
+
+        engine.apply_iter(vec![
+            Input::new(TransactionType::Deposit, 1, 2, Some(5.0)),
+            Input::new(TransactionType::Withdrawal, 2, 3, Some(5.0)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+        ]);
+
+        let history = history.lock().unwrap();
+        assert_eq!(vec![ClientId(1)], history.clients().collect::<Vec<_>>());
+        assert_eq!(
+            FixedPoint::from_f64(10.0),
+            history.opening(ClientId(1)).available
+        );
+
+        let bookings: Vec<Booking> = history.bookings(ClientId(1)).collect();
+        assert_eq!(2, bookings.len());
+        assert!(bookings[0].changes_total());
+        assert!(!bookings[1].changes_total());
+        assert_eq!(
+            vec![1, 2],
+            bookings.iter().map(|b| b.seq).collect::<Vec<_>>()
+        );
+
+        let rejected = history.rejected();
+        assert_eq!(1, rejected.len());
+        assert_eq!(ClientId(2), rejected[0].client);
+        assert_eq!("not_enough_available_funds", rejected[0].reason);
+
+        let applied = history.applied(ClientId(1));
+        assert_eq!(None, applied[0].dispute);
+        assert_eq!(Some(DisputeState::Started), applied[1].dispute);
+
+        let closing = applied
+            .iter()
+            .fold(history.opening(ClientId(1)), |balance, applied| {
+                applied.after(balance)
+            });
+        assert_eq!(FixedPoint::from_f64(10.0), closing.available);
+        assert_eq!(FixedPoint::from_f64(5.0), closing.held);
+    }
+}
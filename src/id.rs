@@ -0,0 +1,169 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The id of a client, and so of its account
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ClientId(pub u64);
+
+impl Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim().parse().map(ClientId)
+    }
+}
+
+impl From<u64> for ClientId {
+    fn from(id: u64) -> Self {
+        ClientId(id)
+    }
+}
+
+/// The id of a transaction, either a number or a UUID.
+///
+/// Neither allocates, so every id takes the same fixed memory however it was written. Ids are
+/// written back in their usual form, numbers without leading zeros and UUIDs in lowercase
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxId {
+    Number(u64),
+    /// Written as the usual 36 characters, like `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    Uuid(u128),
+}
+
+impl Default for TxId {
+    fn default() -> Self {
+        TxId::Number(0)
+    }
+}
+
+impl From<u64> for TxId {
+    fn from(id: u64) -> Self {
+        TxId::Number(id)
+    }
+}
+
+#[derive(Debug)]
+pub struct TxIdParseError(String);
+
+impl Display for TxIdParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' is neither a number nor a UUID", self.0)
+    }
+}
+
+impl std::error::Error for TxIdParseError {}
+
+impl FromStr for TxId {
+    type Err = TxIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(id) = s.parse() {
+            return Ok(TxId::Number(id));
+        }
+        let groups: Vec<&str> = s.split('-').collect();
+        let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+        let hex = groups.concat();
+        if lengths == [8, 4, 4, 4, 12] && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            if let Ok(id) = u128::from_str_radix(&hex, 16) {
+                return Ok(TxId::Uuid(id));
+            }
+        }
+        Err(TxIdParseError(s.to_string()))
+    }
+}
+
+impl Display for TxId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxId::Number(id) => id.fmt(f),
+            TxId::Uuid(id) => {
+                let hex = format!("{:032x}", id);
+                write!(
+                    f,
+                    "{}-{}-{}-{}-{}",
+                    &hex[0..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..32]
+                )
+            }
+        }
+    }
+}
+
+/// Numbers stay numbers, UUIDs are written as strings
+impl Serialize for TxId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TxId::Number(id) => serializer.serialize_u64(*id),
+            TxId::Uuid(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TxIdVisitor;
+
+        impl<'de> de::Visitor<'de> for TxIdVisitor {
+            type Value = TxId;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a transaction number or UUID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(TxId::Number(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(TxId::Number)
+                    .map_err(|_| E::custom("negative transaction number"))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TxIdVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tx_id_parsing() {
+        assert_eq!(TxId::Number(42), "42".parse().unwrap());
+        let uuid: TxId = "67E55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap();
+        assert!(matches!(uuid, TxId::Uuid(_)));
+        assert_eq!("67e55044-10b1-426f-9247-bb680e5fe0c8", uuid.to_string());
+        assert!("-1".parse::<TxId>().is_err());
+        assert!("67e5504410b1426f9247bb680e5fe0c8".parse::<TxId>().is_err());
+
+        assert_eq!("42", serde_json::to_string(&TxId::Number(42)).unwrap());
+        assert_eq!(
+            uuid,
+            serde_json::from_str(&serde_json::to_string(&uuid).unwrap()).unwrap()
+        );
+        assert_eq!(TxId::Number(7), serde_json::from_str("7").unwrap());
+    }
+}
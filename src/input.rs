@@ -1,22 +1,34 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, pin::Pin, str::FromStr};
 
-use crate::FixedPoint;
+use crate::{
+    id::{ClientId, TxId},
+    {bank, camt, compression, mt940, timestamp::Timestamp, FixedPoint, ParseFixedPointError},
+};
 
-use csv_async::AsyncDeserializer;
-use serde::Deserialize;
-use tokio::fs::File;
+use csv_async::StringRecord;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::{
+    fs::File,
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+};
+use tokio_stream::{wrappers::LinesStream, Stream, StreamExt};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Input {
     /// This is the type of the input, it can only be a fixed amount of values
     r#type: TransactionType,
 
     /// client ID number
-    client: u16,
+    client: ClientId,
 
-    tx: u32,
-    /// These are fixed point numbers, but we will treat them as f64 for simple serialization and deserialization
-    amount: Option<f64>,
+    tx: TxId,
+    /// Written as exact decimal text, read from text or a number
+    #[serde(default, deserialize_with = "amount", serialize_with = "amount_text")]
+    amount: Option<FixedPoint>,
+
+    /// Optional point in time of the transaction, either RFC 3339 or epoch milliseconds
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
 }
 
 impl Input {
@@ -26,11 +38,7 @@ impl Input {
             TransactionType::Deposit | TransactionType::Withdrawal => {
                 // We dont allow negative values, since that is basically what the type is declaring
                 if let Some(amount) = self.amount {
-                    if amount > 0.0 {
-                        true
-                    } else {
-                        false
-                    }
+                    amount > FixedPoint::default()
                 } else {
                     false
                 }
@@ -38,11 +46,19 @@ impl Input {
             TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
                 self.amount.is_none()
             }
+            // A limit of zero is the same as having no credit line at all
+            TransactionType::SetLimit => {
+                if let Some(amount) = self.amount {
+                    amount >= FixedPoint::default()
+                } else {
+                    false
+                }
+            }
         }
     }
 
     /// Get the input's client.
-    pub fn client(&self) -> u16 {
+    pub fn client(&self) -> ClientId {
         self.client
     }
 
@@ -53,44 +69,536 @@ impl Input {
 
     /// Get the input's amount
     pub fn amount_as_fp(&self) -> Option<FixedPoint> {
-        self.amount.map(|v| FixedPoint::from_f64(v))
+        self.amount
     }
 
     /// Get the input's tx.
-    pub fn tx(&self) -> u32 {
+    pub fn tx(&self) -> TxId {
         self.tx
     }
 
-    /// only to create easier test transactions
-    #[allow(unused)]
-    pub fn new(r#type: TransactionType, client: u16, tx: u32, amount: Option<f64>) -> Self {
+    /// Creates an input with a numbered tx, the amount is rounded to the decimals of [`FixedPoint`]
+    pub fn new(r#type: TransactionType, client: u64, tx: u64, amount: Option<f64>) -> Self {
+        Self::with_ids(
+            r#type,
+            ClientId(client),
+            TxId::Number(tx),
+            amount.map(FixedPoint::from_f64),
+        )
+    }
+
+    /// Creates an input with ids of any kind and an exact amount
+    pub fn with_ids(
+        r#type: TransactionType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<FixedPoint>,
+    ) -> Self {
         Self {
             r#type,
             client,
             tx,
             amount,
+            timestamp: None,
         }
     }
 
-    /// Get the input's amount.
-    #[allow(unused)]
+    /// Attach a timestamp to the input
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Get the input's timestamp, if the input had one
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    /// Get the input's amount as a float, which is not exact, see [`Input::amount_as_fp`]
     pub fn amount(&self) -> Option<f64> {
-        self.amount
+        self.amount.map(FixedPoint::to_f64)
+    }
+}
+
+/// The names of the fields of an input, in the order a file without a header has them by default
+pub const FIELDS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+
+/// The names of the input columns, for files where they differ from the field names of [`Input`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Columns {
+    pub r#type: String,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+    pub timestamp: String,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            r#type: "type".to_string(),
+            client: "client".to_string(),
+            tx: "tx".to_string(),
+            amount: "amount".to_string(),
+            timestamp: "timestamp".to_string(),
+        }
+    }
+}
+
+impl Columns {
+    /// The field name a column of the file is read into, columns not listed keep their name
+    fn field<'c>(&self, column: &'c str) -> &'c str {
+        let fields = [
+            (&self.r#type, "type"),
+            (&self.client, "client"),
+            (&self.tx, "tx"),
+            (&self.amount, "amount"),
+            (&self.timestamp, "timestamp"),
+        ];
+        fields
+            .into_iter()
+            .find(|(name, _)| *name == column)
+            .map_or(column, |(_, field)| field)
+    }
+}
+
+/// How the input csv file is read, each partner sends a slightly different dialect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputOptions {
+    pub delimiter: u8,
+    /// Whether whitespace around fields and headers is removed
+    pub trim: bool,
+    /// The quote character around fields containing the delimiter
+    pub quote: u8,
+    /// Whether quotes are special at all, when false they are read as part of the field
+    pub quoting: bool,
+    /// Whether the first line is a header naming the columns
+    pub has_headers: bool,
+    /// Names of the columns in the header
+    pub columns: Columns,
+    /// The fields of the columns by position, only used for files without a header
+    pub order: Vec<String>,
+    /// Other spellings of the transaction types, like `DEP` for a deposit
+    pub types: BTreeMap<String, TransactionType>,
+    /// The format of the file, the other options only matter for csv
+    pub format: InputFormat,
+    /// The client every bank account belongs to, for bank statements
+    pub accounts: BTreeMap<String, ClientId>,
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            trim: true,
+            quote: b'"',
+            quoting: true,
+            has_headers: true,
+            columns: Columns::default(),
+            order: FIELDS.iter().map(|field| field.to_string()).collect(),
+            types: BTreeMap::new(),
+            format: InputFormat::Auto,
+            accounts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InputError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The line is not valid csv, or a field could not be read
+    Csv(csv_async::Error),
+    /// The line is not valid json, or not shaped like an input
+    Json(serde_json::Error),
+    /// The type is not one of the transaction types, nor another spelling of one
+    UnknownType(String),
+    /// The amount of a csv line is not a decimal with at most 4 decimals
+    Amount(String, ParseFixedPointError),
+    /// The camt bank statement could not be read
+    Camt(camt::CamtError),
+    /// The MT940 bank statement could not be read
+    Mt940(mt940::Mt940Error),
+    /// An entry of a bank statement could not be turned into an input
+    Import(bank::ImportError),
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputError::Io(e) => write!(f, "{}", e),
+            InputError::Csv(e) => write!(f, "{}", e),
+            InputError::Json(e) => write!(f, "{}", e),
+            InputError::UnknownType(r#type) => write!(f, "unknown transaction type: {}", r#type),
+            InputError::Amount(amount, e) => write!(f, "invalid amount `{}`: {}", amount, e),
+            InputError::Camt(e) => write!(f, "{}", e),
+            InputError::Mt940(e) => write!(f, "{}", e),
+            InputError::Import(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+impl From<io::Error> for InputError {
+    fn from(e: io::Error) -> Self {
+        InputError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for InputError {
+    fn from(e: serde_json::Error) -> Self {
+        InputError::Json(e)
+    }
+}
+
+impl From<camt::CamtError> for InputError {
+    fn from(e: camt::CamtError) -> Self {
+        InputError::Camt(e)
+    }
+}
+
+impl From<mt940::Mt940Error> for InputError {
+    fn from(e: mt940::Mt940Error) -> Self {
+        InputError::Mt940(e)
+    }
+}
+
+impl From<bank::ImportError> for InputError {
+    fn from(e: bank::ImportError) -> Self {
+        InputError::Import(e)
+    }
+}
+
+impl From<csv_async::Error> for InputError {
+    fn from(e: csv_async::Error) -> Self {
+        InputError::Csv(e)
+    }
+}
+
+/// A line of the input file, before the type is looked up
+#[derive(Debug, Deserialize)]
+struct Row {
+    r#type: String,
+    client: ClientId,
+    tx: TxId,
+    #[serde(default, deserialize_with = "amount")]
+    amount: Option<FixedPoint>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+/// Amounts are numbers, or strings holding a number as some json exports write them. Strings are
+/// read exactly, numbers are rounded to the decimals of [`FixedPoint`]
+fn amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<FixedPoint>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Number(f64),
+        Text(String),
+    }
+    match Option::<Amount>::deserialize(deserializer)? {
+        Some(Amount::Number(amount)) => Ok(Some(FixedPoint::from_f64(amount))),
+        Some(Amount::Text(text)) if !text.trim().is_empty() => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+fn amount_text<S: Serializer>(
+    amount: &Option<FixedPoint>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match amount {
+        Some(amount) => serializer.collect_str(amount),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl Row {
+    fn into_input(self, types: &BTreeMap<String, TransactionType>) -> Result<Input, InputError> {
+        let r#type = match types.get(&self.r#type) {
+            Some(r#type) => *r#type,
+            None => self
+                .r#type
+                .parse()
+                .map_err(|_| InputError::UnknownType(self.r#type))?,
+        };
+        Ok(Input {
+            r#type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+/// The formats inputs can be read from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Json when the file starts with `[` or `{`, a camt statement when it starts with `<`, an
+    /// MT940 statement when it starts with `{1:` or `:20:`, otherwise csv
+    Auto,
+    Csv,
+    /// A single json array of objects shaped like an [`Input`]
+    Json,
+    /// One json object shaped like an [`Input`] per line
+    JsonLines,
+    /// An ISO 20022 camt.053 bank statement or camt.054 notification
+    Camt,
+    /// A SWIFT MT940 customer statement
+    Mt940,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(InputFormat::Auto),
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "jsonl" | "jsonlines" => Ok(InputFormat::JsonLines),
+            "camt" | "xml" => Ok(InputFormat::Camt),
+            "mt940" => Ok(InputFormat::Mt940),
+            other => Err(format!("unknown input format: {}", other)),
+        }
+    }
+}
+
+/// The inputs read from a file, either as an input or the reason the line could not be read
+pub type Inputs<'a> = Pin<Box<dyn Stream<Item = Result<Input, InputError>> + Send + 'a>>;
+
+/// Reads inputs from a file in the format and dialect described by the options
+pub struct InputReader<R> {
+    reader: BufReader<R>,
+    options: InputOptions,
+}
+
+impl InputReader<compression::Reader> {
+    /// Opens the input file, decompressing it on the fly if it is compressed
+    pub async fn open(pathname: &str, options: InputOptions) -> io::Result<Self> {
+        Ok(Self::from_reader(
+            compression::open(pathname).await?,
+            options,
+        ))
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin + Send + 'a> InputReader<R> {
+    pub fn from_reader(reader: R, options: InputOptions) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            options,
+        }
+    }
+
+    /// Every input of the file, after the header for csv files. Whatever the format, every line
+    /// goes through the same checks and type spellings
+    pub async fn inputs(mut self) -> Result<Inputs<'a>, InputError> {
+        let format = match self.options.format {
+            InputFormat::Auto => {
+                // peeking does not consume anything, the first buffer is plenty to tell
+                let head = self.reader.fill_buf().await?;
+                let start = head.iter().position(|byte| !byte.is_ascii_whitespace());
+                let head = &head[start.unwrap_or(head.len())..];
+                match head {
+                    [b'{', b'1', b':', ..] | [b':', b'2', b'0', b':', ..] => InputFormat::Mt940,
+                    [b'[', ..] => InputFormat::Json,
+                    [b'{', ..] => InputFormat::JsonLines,
+                    [b'<', ..] => InputFormat::Camt,
+                    _ => InputFormat::Csv,
+                }
+            }
+            format => format,
+        };
+        let types = self.options.types.clone();
+        match format {
+            InputFormat::JsonLines => {
+                let lines = LinesStream::new(self.reader.lines());
+                let inputs = lines
+                    .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                    .map(move |line| {
+                        let row: Row = serde_json::from_str(&line?)?;
+                        row.into_input(&types)
+                    });
+                Ok(Box::pin(inputs))
+            }
+            InputFormat::Json => {
+                // an array can not be read one element at a time, but every element is checked
+                // on its own, so a single bad one does not fail the others
+                let mut content = Vec::new();
+                self.reader.read_to_end(&mut content).await?;
+                let values: Vec<serde_json::Value> = serde_json::from_slice(&content)?;
+                let inputs = values.into_iter().map(move |value| {
+                    let row: Row = serde_json::from_value(value)?;
+                    row.into_input(&types)
+                });
+                Ok(Box::pin(tokio_stream::iter(inputs)))
+            }
+            InputFormat::Camt | InputFormat::Mt940 => {
+                let mut content = String::new();
+                self.reader.read_to_string(&mut content).await?;
+                let entries = if format == InputFormat::Camt {
+                    camt::parse(&content)?
+                } else {
+                    mt940::parse(&content)?
+                };
+                let importer = bank::Importer::new(self.options.accounts);
+                Ok(Box::pin(tokio_stream::iter(import(&importer, &entries))))
+            }
+            InputFormat::Csv | InputFormat::Auto => self.csv_inputs(types).await,
+        }
+    }
+
+    async fn csv_inputs(
+        self,
+        types: BTreeMap<String, TransactionType>,
+    ) -> Result<Inputs<'a>, InputError> {
+        let options = self.options;
+        let trim = if options.trim {
+            csv_async::Trim::All
+        } else {
+            csv_async::Trim::None
+        };
+        let mut rdr = csv_async::AsyncReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .trim(trim)
+            .quote(options.quote)
+            .quoting(options.quoting)
+            .has_headers(options.has_headers)
+            .flexible(true)
+            .create_reader(self.reader);
+
+        // The rows are matched to fields by name, so columns can come in any order
+        let headers: StringRecord = if options.has_headers {
+            let headers = rdr.headers().await?;
+            headers
+                .iter()
+                .map(|column| options.columns.field(column))
+                .collect()
+        } else {
+            options.order.iter().collect()
+        };
+        // csv takes every field that looks like a number for a float, so the amount is parsed from
+        // its text instead, to keep it exact
+        let amount_column = headers.iter().position(|field| field == "amount");
+        let inputs = rdr.into_records().map(move |record| {
+            let record = record?;
+            let mut row: Row = record.deserialize(Some(&headers))?;
+            if let Some(text) = amount_column.and_then(|column| record.get(column)) {
+                let text = text.trim();
+                row.amount = match text {
+                    "" => None,
+                    _ => Some(
+                        text.parse()
+                            .map_err(|e| InputError::Amount(text.to_string(), e))?,
+                    ),
+                };
+            }
+            row.into_input(&types)
+        });
+        Ok(Box::pin(inputs))
+    }
+}
+
+/// The inputs for the entries of a bank statement
+fn import(importer: &bank::Importer, entries: &[bank::Entry]) -> Vec<Result<Input, InputError>> {
+    let mut inputs = Vec::new();
+    for entry in entries {
+        match importer.inputs(entry) {
+            Ok(imported) => inputs.extend(imported.into_iter().map(Ok)),
+            Err(e) => inputs.push(Err(e.into())),
+        }
+    }
+    inputs
+}
+
+/// A line of the credit limit configuration file, the limit is text so it is read exactly
+#[derive(Debug, Deserialize)]
+struct CreditLimit {
+    client: ClientId,
+    limit: String,
+}
+
+#[derive(Debug)]
+pub enum LimitsError {
+    /// The file could not be read
+    Io(io::Error),
+    /// A line is not valid csv, or has no client id or limit
+    Csv(csv_async::Error),
+    /// The limit on the line is not an amount or negative, the string says which
+    Invalid(u64, String),
+}
+
+impl Display for LimitsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitsError::Io(e) => write!(f, "could not read limits file: {}", e),
+            LimitsError::Csv(e) => write!(f, "invalid limits file: {}", e),
+            LimitsError::Invalid(line, message) => {
+                write!(f, "invalid limits file: line {}: {}", line, message)
+            }
+        }
     }
 }
 
-pub async fn create_input_deserializer(pathname: &str) -> AsyncDeserializer<tokio::fs::File> {
-    let file = File::open(pathname).await.unwrap();
+impl std::error::Error for LimitsError {}
 
-    let rdr = csv_async::AsyncReaderBuilder::new()
+impl From<io::Error> for LimitsError {
+    fn from(e: io::Error) -> Self {
+        LimitsError::Io(e)
+    }
+}
+
+impl From<csv_async::Error> for LimitsError {
+    fn from(e: csv_async::Error) -> Self {
+        LimitsError::Csv(e)
+    }
+}
+
+/// Reads the per client credit limits from a csv file with the columns `client, limit`
+pub async fn read_credit_limits(
+    pathname: &str,
+) -> Result<BTreeMap<ClientId, FixedPoint>, LimitsError> {
+    credit_limits(File::open(pathname).await?).await
+}
+
+/// Reads the per client credit limits from csv with the columns `client, limit`. Unlike
+/// transactions, a bad line fails the whole file, naming the line
+pub async fn credit_limits<R: AsyncRead + Unpin + Send>(
+    reader: R,
+) -> Result<BTreeMap<ClientId, FixedPoint>, LimitsError> {
+    let mut rdr = csv_async::AsyncReaderBuilder::new()
         .delimiter(b',')
         .trim(csv_async::Trim::All)
-        .flexible(true)
-        .create_deserializer(file);
-    rdr
+        .create_deserializer(reader);
+
+    let mut limits = BTreeMap::new();
+    let mut records = rdr.deserialize_with_pos::<CreditLimit>();
+    while let Some((record, position)) = records.next().await {
+        let record = record?;
+        let invalid = |message| LimitsError::Invalid(position.line(), message);
+        let limit: FixedPoint = record
+            .limit
+            .parse()
+            .map_err(|e| invalid(format!("`{}` is not a valid amount: {}", record.limit, e)))?;
+        if limit < FixedPoint::from_f64(0.0) {
+            return Err(invalid(format!(
+                "the limit can not be negative, got {}",
+                record.limit
+            )));
+        }
+        limits.insert(record.client, limit);
+    }
+    Ok(limits)
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -98,6 +606,25 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Sets the credit limit of the client to the amount
+    #[serde(rename = "set_limit")]
+    SetLimit,
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "set_limit" => Ok(TransactionType::SetLimit),
+            other => Err(format!("unknown transaction type: {}", other)),
+        }
+    }
 }
 
 impl Display for TransactionType {
@@ -108,6 +635,7 @@ impl Display for TransactionType {
             TransactionType::Dispute => "dispute",
             TransactionType::Resolve => "resolve",
             TransactionType::Chargeback => "chargeback",
+            TransactionType::SetLimit => "set_limit",
         };
         f.write_str(s)
     }
@@ -116,20 +644,238 @@ impl Display for TransactionType {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio_stream::StreamExt;
+
+    async fn read_all(pathname: &str, options: InputOptions) -> Vec<Input> {
+        let reader = InputReader::open(pathname, options).await.unwrap();
+        let inputs = reader.inputs().await.unwrap();
+        // just crash on errors in input for these tests
+        inputs.map(|e| e.unwrap()).collect().await
+    }
 
     #[tokio::test]
     async fn parsing_input_works() {
-        let mut rdr = create_input_deserializer("testdata/input.csv").await;
+        let inputs = read_all("testdata/input.csv", InputOptions::default()).await;
+        let valid = inputs.iter().filter(|tx| tx.valid()).count();
+
+        assert_eq!(8, valid);
+    }
+
+    #[tokio::test]
+    async fn parsing_csv_amounts_is_exact() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,0.1234\ndeposit,1,2,1.23456\ndispute,1,1,\n";
+        let reader = InputReader::from_reader(csv.as_bytes(), InputOptions::default());
+        let results: Vec<_> = reader.inputs().await.unwrap().collect().await;
+
+        assert_eq!(
+            Some("0.1234".parse().unwrap()),
+            results[0].as_ref().unwrap().amount_as_fp()
+        );
+        assert_eq!(
+            "invalid amount `1.23456`: more than 4 decimals",
+            results[1].as_ref().err().unwrap().to_string()
+        );
+        assert_eq!(None, results[2].as_ref().unwrap().amount_as_fp());
+    }
+
+    #[tokio::test]
+    async fn parsing_timestamped_input_works() {
+        let inputs = read_all("testdata/input_timestamps.csv", InputOptions::default()).await;
+
+        assert_eq!(5, inputs.len());
+        assert_eq!(
+            Some(Timestamp::from_millis(1650000000000)),
+            inputs[0].timestamp()
+        );
+        assert_eq!(inputs[0].timestamp(), inputs[1].timestamp());
+        assert_eq!(None, inputs[2].timestamp());
+    }
+
+    #[tokio::test]
+    async fn parsing_renamed_columns_works() {
+        let options = InputOptions {
+            delimiter: b';',
+            columns: Columns {
+                r#type: "kind".to_string(),
+                client: "customer".to_string(),
+                tx: "id".to_string(),
+                amount: "value".to_string(),
+                ..Columns::default()
+            },
+            ..InputOptions::default()
+        };
+        let inputs = read_all("testdata/input_columns.csv", options).await;
+
+        assert_eq!(3, inputs.len());
+        assert_eq!(TransactionType::Withdrawal, *inputs[1].r#type());
+        assert_eq!(Some(2.5), inputs[0].amount());
+        assert!(inputs[2].valid());
+    }
 
-        let amount: Vec<Input> = rdr
-            .deserialize()
-            // just crash on errors in input for this test
-            .map(|e: Result<Input, _>| e.unwrap())
-            .filter(|tx| tx.valid())
-            .collect()
-            .await;
+    #[tokio::test]
+    async fn parsing_headerless_dialect_works() {
+        let options = InputOptions {
+            delimiter: b'\t',
+            quote: b'\'',
+            has_headers: false,
+            order: ["tx", "client", "amount", "type"]
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+            types: BTreeMap::from([
+                ("DEP".to_string(), TransactionType::Deposit),
+                ("WD".to_string(), TransactionType::Withdrawal),
+            ]),
+            ..InputOptions::default()
+        };
+        let inputs = read_all("testdata/input_headerless.tsv", options).await;
+
+        assert_eq!(4, inputs.len());
+        assert_eq!(TransactionType::Deposit, *inputs[0].r#type());
+        assert_eq!(TxId::Number(7), inputs[0].tx());
+        assert_eq!(ClientId(2), inputs[0].client());
+        assert_eq!(Some(1.5), inputs[1].amount());
+        assert_eq!(TransactionType::Withdrawal, *inputs[2].r#type());
+        // the canonical names still work next to the other spellings
+        assert_eq!(TransactionType::Dispute, *inputs[3].r#type());
+
+        let reader = InputReader::from_reader(
+            &b"type,client,tx,amount\nPAY,1,2,1.0\n"[..],
+            InputOptions::default(),
+        );
+        let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+        assert!(matches!(&rows[0], Err(InputError::UnknownType(t)) if t == "PAY"));
+    }
+
+    #[tokio::test]
+    async fn parsing_json_input_works() {
+        let options = InputOptions {
+            types: BTreeMap::from([("DEP".to_string(), TransactionType::Deposit)]),
+            ..InputOptions::default()
+        };
+        let reader = InputReader::open("testdata/input.jsonl", options)
+            .await
+            .unwrap();
+        let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+
+        // the blank line is skipped, the line without a tx is not an input
+        assert_eq!(4, rows.len());
+        let inputs: Vec<&Input> = rows.iter().filter_map(|row| row.as_ref().ok()).collect();
+        assert_eq!(3, inputs.len());
+        assert_eq!(
+            Some(Timestamp::from_millis(1650000000000)),
+            inputs[0].timestamp()
+        );
+        assert_eq!(TransactionType::Deposit, *inputs[1].r#type());
+        assert_eq!(Some(2.25), inputs[1].amount());
+        assert!(inputs[2].valid());
+        assert!(matches!(rows[3], Err(InputError::Json(_))));
+
+        let reader = InputReader::open("testdata/input.json", InputOptions::default())
+            .await
+            .unwrap();
+        let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+        assert_eq!(4, rows.len());
+        assert_eq!(Some(1.5), rows[0].as_ref().unwrap().amount());
+        assert_eq!(
+            Some(Timestamp::from_millis(1650000000000)),
+            rows[1].as_ref().unwrap().timestamp()
+        );
+        assert!(matches!(&rows[2], Err(InputError::UnknownType(t)) if t == "refund"));
+        assert!(rows[3].as_ref().unwrap().valid());
+
+        // forcing csv on a json file reads it as a csv file with a strange header
+        let options = InputOptions {
+            format: InputFormat::Csv,
+            ..InputOptions::default()
+        };
+        let reader = InputReader::open("testdata/input.json", options)
+            .await
+            .unwrap();
+        let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+        assert!(rows.iter().all(|row| row.is_err()));
+    }
+
+    #[tokio::test]
+    async fn parsing_bank_statements_works() {
+        let accounts = bank::read_accounts("testdata/bank_accounts.csv").unwrap();
+        let mut inputs = Vec::new();
+        for path in [
+            "testdata/camt053.xml",
+            "testdata/camt054.xml",
+            "testdata/mt940.sta",
+        ] {
+            let options = InputOptions {
+                accounts: accounts.clone(),
+                ..InputOptions::default()
+            };
+            let reader = InputReader::open(path, options).await.unwrap();
+            let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+            inputs.extend(rows.into_iter().map(Result::unwrap));
+        }
+        let summary: Vec<_> = inputs
+            .iter()
+            .map(|input| (*input.r#type(), input.client().0))
+            .collect();
+        assert_eq!(
+            vec![
+                (TransactionType::Deposit, 1),
+                (TransactionType::Withdrawal, 1),
+                (TransactionType::Dispute, 1),
+                (TransactionType::Chargeback, 1),
+                (TransactionType::Deposit, 1),
+                (TransactionType::Deposit, 2),
+                (TransactionType::Deposit, 1),
+                (TransactionType::Withdrawal, 1),
+                (TransactionType::Dispute, 1),
+                (TransactionType::Chargeback, 1),
+                (TransactionType::Deposit, 1),
+                (TransactionType::Deposit, 2),
+            ],
+            summary
+        );
+        let tx: Vec<TxId> = inputs.iter().map(Input::tx).collect();
+        // the return is applied to the deposit it returns
+        assert_eq!([tx[0], tx[0]], [tx[2], tx[3]]);
+        let payments = [tx[0], tx[1], tx[4], tx[5]];
+        assert!((1..4).all(|i| !payments[..i].contains(&payments[i])));
+        // the same payments again as MT940 have the same ids, as the references are the same
+        assert_eq!(&tx[..5], &tx[6..11]);
+        assert_ne!(tx[5], tx[11]);
+        assert_eq!(Some("150.25".parse().unwrap()), inputs[0].amount_as_fp());
 
-        assert_eq!(8, amount.len());
+        // without an account lookup no entry belongs to a client
+        let reader = InputReader::open("testdata/camt054.xml", InputOptions::default())
+            .await
+            .unwrap();
+        let rows: Vec<_> = reader.inputs().await.unwrap().collect().await;
+        assert!(matches!(rows[0], Err(InputError::Import(_))));
+    }
+
+    #[tokio::test]
+    async fn parsing_credit_limits_works() {
+        let limits = read_credit_limits("testdata/limits.csv").await.unwrap();
+
+        assert_eq!(2, limits.len());
+        assert_eq!(100.0, limits[&ClientId(1)]);
+        assert_eq!(
+            "2500.0001".parse::<FixedPoint>().unwrap(),
+            limits[&ClientId(3)]
+        );
+
+        let error = |csv: &'static str| async move {
+            credit_limits(csv.as_bytes()).await.unwrap_err().to_string()
+        };
+        assert_eq!(
+            "invalid limits file: line 3: the limit can not be negative, got -5.0",
+            error("client, limit\n1, 100.0\n2, -5.0\n").await
+        );
+        assert!(error("client, limit\n1, 0.00001\n")
+            .await
+            .starts_with("invalid limits file: line 2: `0.00001` is not a valid amount"));
+        assert!(read_credit_limits("testdata/missing.csv")
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with("could not read limits file:"));
     }
 }
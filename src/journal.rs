@@ -0,0 +1,353 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Seek, SeekFrom},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    id::{ClientId, TxId},
+    FixedPoint,
+};
+
+/// The ledgers funds can be moved between for a single client
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ledger {
+    /// The client's available funds
+    Available,
+    /// The client's funds held during a dispute
+    Held,
+    /// Everything outside the engine, deposits come from here and withdrawals go here
+    External,
+    /// Funds that have been charged back and are lost to the client
+    ChargebackLoss,
+}
+
+impl Display for Ledger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Ledger::Available => "available",
+            Ledger::Held => "held",
+            Ledger::External => "external",
+            Ledger::ChargebackLoss => "chargeback_loss",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A balanced movement of funds, the debited ledger grows with the amount and the credited shrinks
+/// with the same amount, so the sum over all ledgers always stays zero
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Movement {
+    pub debit: Ledger,
+    pub credit: Ledger,
+    pub amount: FixedPoint,
+}
+
+impl Movement {
+    pub fn new(debit: Ledger, credit: Ledger, amount: FixedPoint) -> Self {
+        Self {
+            debit,
+            credit,
+            amount,
+        }
+    }
+}
+
+/// A line in the journal file
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    /// position in the journal, strictly increasing
+    seq: u64,
+    client: ClientId,
+    /// the transaction that caused the movement
+    tx: TxId,
+    debit: Ledger,
+    credit: Ledger,
+    /// exact decimal text, so replaying gives back the balances to the last decimal
+    #[serde(serialize_with = "decimal_text", deserialize_with = "decimal")]
+    amount: FixedPoint,
+}
+
+fn decimal_text<S: Serializer>(amount: &FixedPoint, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(amount)
+}
+
+fn decimal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FixedPoint, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Append-only journal of every movement of funds, written as csv
+pub struct Journal<W: io::Write> {
+    writer: csv::Writer<W>,
+    seq: u64,
+    /// movements already in the file for inputs that are applied again, they are not written twice
+    recorded: BTreeSet<(ClientId, TxId, Ledger, Ledger)>,
+}
+
+impl Journal<File> {
+    /// Opens the journal file, creating it if it does not exist yet. Entries are appended after the
+    /// ones of earlier runs, numbered on from the last of them
+    pub fn open(pathname: &str) -> csv::Result<Self> {
+        Self::open_replaying(pathname, &BTreeSet::new())
+    }
+
+    /// Opens the journal like [`Journal::open`], for a run that applies the transactions in
+    /// `replayed` again after a crash. Their movements that already made it into the journal are
+    /// not written a second time
+    pub fn open_replaying(
+        pathname: &str,
+        replayed: &BTreeSet<(ClientId, TxId)>,
+    ) -> csv::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(pathname)?;
+        // a line cut off by a crash is dropped, like the rest of the movements it never wrote
+        let complete = complete_len(&file)?;
+        file.set_len(complete)?;
+        (&file).seek(SeekFrom::Start(0))?;
+
+        let mut seq = 0;
+        let mut recorded = BTreeSet::new();
+        for entry in csv::Reader::from_reader(&file).deserialize() {
+            let entry: JournalEntry = entry?;
+            seq = entry.seq;
+            if replayed.contains(&(entry.client, entry.tx)) {
+                recorded.insert((entry.client, entry.tx, entry.debit, entry.credit));
+            }
+        }
+        let writer = csv::WriterBuilder::new()
+            .has_headers(complete == 0)
+            .from_writer(file);
+        Ok(Self {
+            writer,
+            seq,
+            recorded,
+        })
+    }
+}
+
+/// The length of the file up to and including its last line break
+fn complete_len(file: &File) -> io::Result<u64> {
+    let mut reader = io::BufReader::new(file);
+    let mut line = Vec::new();
+    let (mut read, mut complete) = (0, 0);
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n == 0 {
+            return Ok(complete);
+        }
+        read += n as u64;
+        if line.ends_with(b"\n") {
+            complete = read;
+        }
+    }
+}
+
+impl<W: io::Write> Journal<W> {
+    pub fn from_writer(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            seq: 0,
+            recorded: BTreeSet::new(),
+        }
+    }
+
+    /// Appends the movement caused by transaction `tx` for `client` to the journal
+    pub fn record(&mut self, client: ClientId, tx: TxId, movement: Movement) -> csv::Result<()> {
+        if self
+            .recorded
+            .remove(&(client, tx, movement.debit, movement.credit))
+        {
+            return Ok(());
+        }
+        self.seq += 1;
+        self.writer.serialize(JournalEntry {
+            seq: self.seq,
+            client,
+            tx,
+            debit: movement.debit,
+            credit: movement.credit,
+            amount: movement.amount,
+        })
+    }
+
+    /// Flushes the journal, and gives back the underlying writer
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer
+            .into_inner()
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    /// The seq of the last entry, also of earlier runs
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Recomputes the balance of every ledger for every client by replaying a journal
+pub fn replay<R: io::Read>(
+    reader: R,
+) -> csv::Result<BTreeMap<ClientId, BTreeMap<Ledger, FixedPoint>>> {
+    let mut balances: BTreeMap<ClientId, BTreeMap<Ledger, FixedPoint>> = BTreeMap::new();
+    for entry in csv::Reader::from_reader(reader).deserialize() {
+        let entry: JournalEntry = entry?;
+        let amount = entry.amount;
+        let ledgers = balances.entry(entry.client).or_default();
+        *ledgers
+            .entry(entry.debit)
+            .or_insert(FixedPoint::from_f64(0.0)) += amount;
+        *ledgers
+            .entry(entry.credit)
+            .or_insert(FixedPoint::from_f64(0.0)) -= amount;
+    }
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accounts::{Account, AccountStorage, Applied},
+        input::{Input, TransactionType},
+        snapshot,
+    };
+
+    #[tokio::test]
+    async fn journal_replay_matches_account() {
+        let mut account = Account::new();
+        let mut journal = Journal::from_writer(Vec::new());
+
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(5.1234)),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(2.0)),
+            // too large, nothing should be journaled
+            Input::new(TransactionType::Withdrawal, 1, 4, Some(200.0)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+            Input::new(TransactionType::Resolve, 1, 2, None),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::new(TransactionType::Chargeback, 1, 1, None),
+        ];
+        for input in inputs {
+            let tx = input.tx();
+            if let Ok(Some(movement)) = account.handle_transaction(input) {
+                journal.record(ClientId(1), tx, movement).unwrap();
+            }
+        }
+
+        let written = journal.into_inner().unwrap();
+        let text = String::from_utf8(written.clone()).unwrap();
+        assert!(text.contains(",available,external,5.1234\n"));
+        let balances = replay(written.as_slice()).unwrap();
+        let ledgers = &balances[&ClientId(1)];
+
+        assert_eq!(account.available(), ledgers[&Ledger::Available]);
+        assert_eq!(account.held(), ledgers[&Ledger::Held]);
+        assert_eq!(50.0, ledgers[&Ledger::ChargebackLoss]);
+
+        let sum = ledgers
+            .values()
+            .fold(FixedPoint::from_f64(0.0), |sum, v| sum + *v);
+        assert_eq!(0.0, sum);
+    }
+
+    #[tokio::test]
+    async fn journal_appends_across_runs() {
+        let dir = std::env::temp_dir().join("toypa_journal_runs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal_path = dir.join("journal.csv");
+        let journal_path = journal_path.to_str().unwrap();
+        let snapshot_path = dir.join("snapshot.json");
+        let snapshot_path = snapshot_path.to_str().unwrap();
+
+        fn run<'a>(
+            journal_path: &str,
+            mut accounts: AccountStorage<'a>,
+            inputs: Vec<Input>,
+        ) -> AccountStorage<'a> {
+            let mut journal = Journal::open(journal_path).unwrap();
+            for input in inputs {
+                let (client, tx) = (input.client(), input.tx());
+                if let Ok(Applied {
+                    movement: Some(movement),
+                    ..
+                }) = accounts.apply(input)
+                {
+                    journal.record(client, tx, movement).unwrap();
+                }
+            }
+            journal.flush().unwrap();
+            accounts
+        }
+
+        let first = run(
+            journal_path,
+            AccountStorage::new(""),
+            vec![
+                Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+                Input::new(TransactionType::Deposit, 2, 2, Some(5.0)),
+                Input::new(TransactionType::Dispute, 1, 1, None),
+            ],
+        );
+        snapshot::save(&first, 3, snapshot_path).unwrap();
+
+        let (resumed, _) = snapshot::load(snapshot_path, "").unwrap();
+        let second = run(
+            journal_path,
+            resumed,
+            vec![
+                Input::new(TransactionType::Chargeback, 1, 1, None),
+                Input::new(TransactionType::Withdrawal, 2, 3, Some(1.5)),
+            ],
+        );
+
+        let text = std::fs::read_to_string(journal_path).unwrap();
+        assert_eq!(1, text.matches("seq,").count());
+        assert!(text.contains("\n5,2,3,external,available,1.5000\n"));
+
+        let balances = replay(text.as_bytes()).unwrap();
+        for client in [ClientId(1), ClientId(2)] {
+            let account = second.account(client).unwrap().unwrap();
+            let ledgers = &balances[&client];
+            let ledger = |ledger| ledgers.get(&ledger).copied().unwrap_or_default();
+            assert_eq!(account.available(), ledger(Ledger::Available));
+            assert_eq!(account.held(), ledger(Ledger::Held));
+        }
+
+        // a crash cut the last line short, and the withdrawal is applied again from the log
+        let mut text = text;
+        text.push_str("6,2,4,exte");
+        std::fs::write(journal_path, &text).unwrap();
+        let replayed = BTreeSet::from([(ClientId(2), TxId::Number(3))]);
+        let mut journal = Journal::open_replaying(journal_path, &replayed).unwrap();
+        let withdrawal = Movement::new(Ledger::External, Ledger::Available, "1.5".parse().unwrap());
+        journal
+            .record(ClientId(2), TxId::Number(3), withdrawal)
+            .unwrap();
+        let deposit = Movement::new(Ledger::Available, Ledger::External, "1".parse().unwrap());
+        journal
+            .record(ClientId(2), TxId::Number(4), deposit)
+            .unwrap();
+        journal.flush().unwrap();
+
+        let text = std::fs::read_to_string(journal_path).unwrap();
+        assert_eq!(1, text.matches(",2,3,").count());
+        assert!(
+            text.ends_with("\n5,2,3,external,available,1.5000\n6,2,4,available,external,1.0000\n")
+        );
+    }
+}
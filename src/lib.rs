@@ -0,0 +1,41 @@
+//! A toy payment engine, applying deposits, withdrawals and disputes to client accounts.
+//!
+//! The binaries in this crate are thin wrappers around this library, anything they can do can
+//! also be done by embedding the engine directly.
+
+pub mod accounts;
+pub mod bank;
+pub mod camt;
+#[cfg(feature = "columnar")]
+pub mod columnar;
+pub mod compression;
+pub mod config;
+pub mod engine;
+pub mod history;
+pub mod id;
+pub mod input;
+pub mod journal;
+pub mod merge;
+pub mod mt940;
+pub mod observer;
+pub mod output;
+pub mod policy;
+pub mod simple_fp;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod statement;
+pub mod timestamp;
+pub mod transaction;
+pub mod wal;
+
+pub use accounts::{Account, AccountStorage, DisputeState, TransactionError};
+pub use config::{Config, ConfigError};
+pub use engine::{Engine, Outcome, Summary};
+pub use id::{ClientId, TxId};
+pub use input::{Input, TransactionType};
+pub use observer::Observer;
+pub use policy::Policy;
+pub use simple_fp::{FixedPoint, ParseFixedPointError, Rounding};
+pub use timestamp::Timestamp;
+pub use transaction::Transaction;
@@ -1,42 +1,521 @@
-use tokio_stream::StreamExt;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-mod accounts;
-mod input;
-mod output;
-mod simple_fp;
+#[cfg(feature = "columnar")]
+use toypa::columnar;
+#[cfg(feature = "sqlite")]
+use toypa::sqlite;
+use toypa::{
+    accounts, bank, camt, compression, history::History, input, journal, merge, output, policy,
+    snapshot, statement, timestamp, wal, ClientId, Config, Engine, Timestamp, TxId,
+};
 
-use simple_fp::FixedPoint;
-// mod transaction;
+/// Options that can be given after the input files
+struct Options {
+    /// Config file with the settings of the engine, the other options take precedence over it
+    config: Option<String>,
+    /// Stop processing at the first transaction later than this point in time
+    as_of: Option<Timestamp>,
+    /// Reject disputes on transactions older than this, in milliseconds
+    dispute_window: Option<i64>,
+    /// File with the credit limits for each client
+    limits: Option<String>,
+    /// File to write the journal of every movement of funds to
+    journal: Option<String>,
+    /// Snapshot to resume the account state from
+    resume: Option<String>,
+    /// SQLite database to keep the accounts in, continuing from the accounts stored in it
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<String>,
+    /// File to write a snapshot of the account state to when done
+    snapshot: Option<String>,
+    /// Directory holding the write-ahead log and its checkpoints
+    wal: Option<String>,
+    /// How many inputs to log between each checkpoint
+    checkpoint_every: u64,
+    /// Format of the account balances written when done
+    format: Option<output::Format>,
+    /// File to write the account balances to, instead of stdout
+    output: Option<String>,
+    /// Name of the dispute policy to follow
+    policy: Option<String>,
+    /// Format of the input files, detected from their content if not given
+    input_format: Option<input::InputFormat>,
+    /// File mapping bank accounts to clients, for bank statements
+    accounts: Option<String>,
+    /// File to write a camt.053 statement for every client to when done
+    camt053: Option<String>,
+    /// Currency code of the amounts in the statements
+    currency: String,
+    /// Directory to write the balances, journal and rejections to as columnar files when done
+    #[cfg(feature = "columnar")]
+    columnar: Option<String>,
+    /// Format of the columnar files
+    #[cfg(feature = "columnar")]
+    columnar_format: columnar::ColumnarFormat,
+    /// Order the inputs of several files are applied in
+    merge: merge::MergeOrder,
+    /// Print what happened to the lines of every input file to stderr
+    stats: bool,
+    /// Write the statement of every transaction instead of the balances
+    statement: bool,
+    /// Format of the statement
+    statement_format: statement::StatementFormat,
+    /// Only write the statement of this client
+    client: Option<ClientId>,
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>, statement: bool) -> Self {
+        let mut options = Options {
+            config: None,
+            as_of: None,
+            dispute_window: None,
+            limits: None,
+            journal: None,
+            resume: None,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+            snapshot: None,
+            wal: None,
+            checkpoint_every: 10000,
+            format: None,
+            output: None,
+            policy: None,
+            input_format: None,
+            accounts: None,
+            camt053: None,
+            currency: "EUR".to_string(),
+            #[cfg(feature = "columnar")]
+            columnar: None,
+            #[cfg(feature = "columnar")]
+            columnar_format: columnar::ColumnarFormat::Parquet,
+            merge: merge::MergeOrder::Sequential,
+            stats: false,
+            statement,
+            statement_format: statement::StatementFormat::Text,
+            client: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().expect("Expected a file name after --config");
+                    options.config = Some(path);
+                }
+                "--as-of" => {
+                    let value = args.next().expect("Expected a timestamp after --as-of");
+                    options.as_of = Some(value.parse().expect("Invalid --as-of timestamp"));
+                }
+                "--dispute-window-days" => {
+                    let days: i64 = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("Expected a number of days after --dispute-window-days");
+                    let window = days
+                        .checked_mul(timestamp::MILLIS_PER_DAY)
+                        .expect("The number of days after --dispute-window-days is too large");
+                    options.dispute_window = Some(window);
+                }
+                "--limits" => {
+                    let path = args.next().expect("Expected a file name after --limits");
+                    options.limits = Some(path);
+                }
+                "--journal" => {
+                    let path = args.next().expect("Expected a file name after --journal");
+                    options.journal = Some(path);
+                }
+                "--resume" => {
+                    let path = args.next().expect("Expected a file name after --resume");
+                    options.resume = Some(path);
+                }
+                #[cfg(feature = "sqlite")]
+                "--sqlite" => {
+                    let path = args.next().expect("Expected a file name after --sqlite");
+                    options.sqlite = Some(path);
+                }
+                #[cfg(not(feature = "sqlite"))]
+                "--sqlite" => panic!("--sqlite needs a build with the sqlite feature"),
+                "--snapshot" => {
+                    let path = args.next().expect("Expected a file name after --snapshot");
+                    options.snapshot = Some(path);
+                }
+                "--wal" => {
+                    let path = args.next().expect("Expected a directory after --wal");
+                    options.wal = Some(path);
+                }
+                "--checkpoint-every" => {
+                    options.checkpoint_every = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|n| *n > 0)
+                        .expect("Expected a positive number after --checkpoint-every");
+                }
+                "--format" => {
+                    let format = args.next().expect("Expected a format after --format");
+                    // the statement comes in other formats than the balances
+                    if statement {
+                        options.statement_format =
+                            format.parse().unwrap_or_else(|e| panic!("{}", e));
+                    } else {
+                        options.format = Some(format.parse().unwrap_or_else(|e| panic!("{}", e)));
+                    }
+                }
+                "--client" => {
+                    let client = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("Expected a client id after --client");
+                    options.client = Some(client);
+                }
+                "--output" => {
+                    let path = args.next().expect("Expected a file name after --output");
+                    options.output = Some(path);
+                }
+                "--policy" => {
+                    let name = args.next().expect("Expected a policy name after --policy");
+                    if policy::by_name(&name).is_none() {
+                        panic!("Unknown policy: {}", name);
+                    }
+                    options.policy = Some(name);
+                }
+                "--input-format" => {
+                    let format = args.next().expect("Expected a format after --input-format");
+                    options.input_format = Some(format.parse().unwrap_or_else(|e| panic!("{}", e)));
+                }
+                "--accounts" => {
+                    let path = args.next().expect("Expected a file name after --accounts");
+                    options.accounts = Some(path);
+                }
+                "--camt053" => {
+                    let path = args.next().expect("Expected a file name after --camt053");
+                    options.camt053 = Some(path);
+                }
+                "--currency" => {
+                    options.currency = args.next().expect("Expected a code after --currency");
+                }
+                #[cfg(feature = "columnar")]
+                "--columnar" => {
+                    let dir = args.next().expect("Expected a directory after --columnar");
+                    options.columnar = Some(dir);
+                }
+                #[cfg(feature = "columnar")]
+                "--columnar-format" => {
+                    let format = args
+                        .next()
+                        .expect("Expected a format after --columnar-format");
+                    options.columnar_format = format.parse().unwrap_or_else(|e| panic!("{}", e));
+                }
+                #[cfg(not(feature = "columnar"))]
+                "--columnar" | "--columnar-format" => {
+                    panic!("{} needs a build with the columnar feature", arg)
+                }
+                "--merge" => {
+                    let order = args.next().expect("Expected an order after --merge");
+                    options.merge = order.parse().unwrap_or_else(|e| panic!("{}", e));
+                }
+                "--stats" => options.stats = true,
+                other => panic!("Unknown argument: {}", other),
+            }
+        }
+        options
+    }
+}
+
+/// Applies the input, recording any movement of funds in the journal. True if it was applied
+fn apply(
+    engine: &mut Engine,
+    journal: &mut Option<journal::Journal<std::fs::File>>,
+    input: input::Input,
+) -> bool {
+    // Only the movements of funds are interesting here, failed transactions are skipped
+    let outcome = match engine.apply(input) {
+        Ok(outcome) => outcome,
+        // the run can not go on once the database no longer matches the accounts
+        Err(accounts::TransactionError::Storage(e)) => {
+            panic!("Could not write to database: {}", e)
+        }
+        Err(_) => return false,
+    };
+    if let (Some(journal), Some(movement)) = (journal, outcome.movement) {
+        journal
+            .record(outcome.client, outcome.tx, movement)
+            .expect("Could not write to journal");
+    }
+    true
+}
+
+/// The storage to apply the inputs to, starting from the resumed accounts if there are any.
+///
+/// The database is written after every input, so it is at least as new as anything resumed from,
+/// but resuming means starting over from the resumed state: the database is then rewritten to
+/// match it, dropping whatever it held beyond it
+#[cfg(feature = "sqlite")]
+fn storage<'a>(
+    filename: &'a str,
+    resumed: Option<accounts::AccountStorage<'a>>,
+    options: &Options,
+) -> accounts::AccountStorage<'a> {
+    let store = options.sqlite.as_ref().map(|path| {
+        sqlite::SqliteStore::open(path)
+            .unwrap_or_else(|e| panic!("Could not open database {}: {}", path, e))
+    });
+    match (resumed, store) {
+        (Some(accounts), Some(mut store)) => {
+            store
+                .replace_all(accounts.accounts())
+                .unwrap_or_else(|e| panic!("Could not write to database: {}", e));
+            accounts::AccountStorage::with_store(filename, Box::new(store))
+        }
+        (Some(accounts), None) => accounts,
+        (None, Some(store)) => accounts::AccountStorage::with_store(filename, Box::new(store)),
+        (None, None) => accounts::AccountStorage::new(filename),
+    }
+}
+
+/// The storage to apply the inputs to, starting from the resumed accounts if there are any
+#[cfg(not(feature = "sqlite"))]
+fn storage<'a>(
+    filename: &'a str,
+    resumed: Option<accounts::AccountStorage<'a>>,
+    _options: &Options,
+) -> accounts::AccountStorage<'a> {
+    resumed.unwrap_or_else(|| accounts::AccountStorage::new(filename))
+}
 
 #[tokio::main]
 async fn main() {
-    let filename = std::env::args()
-        .into_iter()
-        .nth(1)
-        .expect("Expected file name as argument");
+    // Every argument up to the first option is an input file, directory or pattern, after the
+    // command if there is one
+    let mut args = std::env::args().skip(1).peekable();
+    let statement = args.next_if(|arg| arg == "statement").is_some();
+    let mut patterns = Vec::new();
+    while let Some(arg) = args.next_if(|arg| !arg.starts_with("--")) {
+        patterns.push(arg);
+    }
+    let options = Options::from_args(args, statement);
+    let paths = merge::expand(&patterns).expect("Could not list input files");
+    let filename = paths
+        .first()
+        .expect("Expected file name as argument")
+        .clone();
 
-    let mut csv_reader = input::create_input_deserializer(&filename).await;
+    let mut config = match &options.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| panic!("{}", e)),
+        None => Config::default(),
+    };
+    // The command line options are applied on top of the config file
+    if let Some(name) = &options.policy {
+        config.policy = name.clone();
+    }
+    if let Some(format) = options.input_format {
+        config.input.format = format;
+    }
+    if options.dispute_window.is_some() {
+        config.dispute_window = options.dispute_window;
+    }
+    if let Some(path) = &options.limits {
+        let limits = input::read_credit_limits(path)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e));
+        config.limits.extend(limits);
+    }
+    let format = options
+        .format
+        .or(config.format)
+        .unwrap_or(output::Format::Csv);
+    let output_path = options.output.as_ref().or(config.output.as_ref());
 
-    let mut accounts = accounts::AccountStorage::new(&filename);
+    if let Some(path) = &options.accounts {
+        config.input.accounts = bank::read_accounts(path)
+            .unwrap_or_else(|e| panic!("Could not read accounts file {}: {}", path, e));
+    }
+    let mut readers = Vec::new();
+    for path in &paths {
+        let reader = input::InputReader::open(path, config.input.clone())
+            .await
+            .unwrap_or_else(|e| panic!("Could not open input file {}: {}", path, e));
+        readers.push(reader);
+    }
 
-    let mut csv_iter = csv_reader.deserialize::<input::Input>();
+    let mut wal = None;
+    let mut recovered = None;
+    if let Some(dir) = &options.wal {
+        let (log, state) = wal::WriteAheadLog::open(dir, &filename, options.checkpoint_every)
+            .unwrap_or_else(|e| panic!("Could not open write-ahead log: {}", e));
+        wal = Some(log);
+        recovered = Some(state);
+    }
+
+    // The inputs logged after the checkpoint were applied before the crash, and their movements
+    // may already be in the journal
+    let replayed: BTreeSet<(ClientId, TxId)> = recovered
+        .iter()
+        .flat_map(|state| &state.tail)
+        .map(|input| (input.client(), input.tx()))
+        .collect();
+    let mut journal = options.journal.as_ref().map(|path| {
+        journal::Journal::open_replaying(path, &replayed).expect("Could not open journal file")
+    });
+
+    // A checkpoint from the write-ahead log is always newer than a snapshot to resume from
+    let checkpoint = recovered.as_mut().and_then(|state| state.accounts.take());
+    let resumed = match (checkpoint, &options.resume) {
+        (Some(accounts), _) => Some(accounts),
+        (None, Some(path)) => Some(
+            snapshot::load(path, &filename)
+                .unwrap_or_else(|e| panic!("Could not load snapshot: {}", e))
+                .0,
+        ),
+        (None, None) => None,
+    };
+    let mut accounts = storage(&filename, resumed, &options);
+    accounts
+        .set_credit_limits(config.limits.clone())
+        .unwrap_or_else(|e| panic!("Could not write to database: {}", e));
+    accounts.set_policy(config.policy());
+    // The statements start from the accounts as they are before any input is applied, the columnar
+    // files take their journal and rejections from the history as well
+    let keep_history = options.camt053.is_some() || options.statement;
+    #[cfg(feature = "columnar")]
+    let keep_history = keep_history || options.columnar.is_some();
+    let history = keep_history.then(|| {
+        let history =
+            History::new(&accounts).unwrap_or_else(|e| panic!("Could not read accounts: {}", e));
+        // numbered on like the journal, so the seq of both journals stands for the same movement
+        let seq = journal.as_ref().map_or(0, journal::Journal::seq);
+        Arc::new(Mutex::new(history.continuing(seq)))
+    });
+    let mut engine = Engine::with_storage(accounts);
+    if let Some(history) = &history {
+        engine.add_observer(history.clone());
+    }
+
+    // Inputs logged before a crash, but not covered by the checkpoint
+    for input in recovered.into_iter().flat_map(|state| state.tail) {
+        apply(&mut engine, &mut journal, input);
+    }
+    let skip = wal.as_ref().map_or(0, |wal| wal.position());
 
-    while let Some(csv_res) = csv_iter.next().await {
+    // anything after the as_of time is not processed, every file is expected to be ordered
+    let mut merger = merge::Merger::new(readers, options.merge)
+        .await
+        .unwrap_or_else(|e| panic!("Could not read input: {}", e))
+        .with_as_of(options.as_of);
+    let mut stats = vec![merge::FileStats::default(); paths.len()];
+    let mut position: u64 = 0;
+
+    while let Some((file, csv_res)) = merger.next().await {
+        // every record counts towards the position, so it always matches the input files,
+        // which are read in the same order on every run
+        position += 1;
+        if position <= skip {
+            continue;
+        }
+        let stats = &mut stats[file];
+        stats.read += 1;
         // every entry is an result, we just ignore any faulty parsed input for this case
         if let Ok(input) = csv_res {
-            // then check the accountstorage for an existing account,
-            // if none exists, we should create one,
-            // and then try to apply the transaction to that account if valid,
+            // then try to apply the transaction to the account of the client if valid,
+            // the account is created if it does not exist yet
             if input.valid() {
-                let entry = accounts
-                    .entry(input.client())
-                    .or_insert(accounts::Account::new());
-
-                // We could check how the transaction went, if we wanted to
-                let _res = entry.handle_transaction(input);
+                if let Some(wal) = &mut wal {
+                    wal.append(position, &input)
+                        .unwrap_or_else(|e| panic!("Could not write to write-ahead log: {}", e));
+                }
+                if apply(&mut engine, &mut journal, input) {
+                    stats.applied += 1;
+                } else {
+                    stats.rejected += 1;
+                }
+                // the journal has to keep up with the log to come back after a crash complete
+                if let (Some(_), Some(journal)) = (&wal, &mut journal) {
+                    journal.flush().expect("Could not write to journal");
+                }
+                if let Some(wal) = &mut wal {
+                    wal.checkpoint_if_due(engine.storage())
+                        .unwrap_or_else(|e| panic!("Could not checkpoint: {}", e));
+                }
+            } else {
+                if let Some(history) = &history {
+                    history.lock().unwrap().reject(&input, "invalid");
+                }
+                stats.rejected += 1;
             }
+        } else {
+            stats.malformed += 1;
+        }
+    }
+
+    if options.stats {
+        for (path, stats) in paths.iter().zip(&stats) {
+            eprintln!("{}: {}", path, stats);
         }
     }
 
-    output::print_from_accounts(accounts);
+    if let Some(journal) = &mut journal {
+        journal.flush().expect("Could not write to journal");
+    }
+
+    // the whole input has been applied, a later run over new input must not skip any of it
+    if let Some(wal) = &mut wal {
+        wal.finish(engine.storage())
+            .unwrap_or_else(|e| panic!("Could not checkpoint: {}", e));
+    }
+
+    if let Some(path) = &options.snapshot {
+        snapshot::save(engine.storage(), position, path)
+            .unwrap_or_else(|e| panic!("Could not write snapshot: {}", e));
+    }
+
+    let precision = config.precision;
+    if let (Some(path), Some(history)) = (&options.camt053, &history) {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Clock is before 1970");
+        let statement_options = camt::StatementOptions {
+            currency: options.currency.clone(),
+            precision,
+            created: Timestamp::from_millis(created.as_millis() as i64),
+        };
+        let mut writer = compression::create(path).expect("Could not create statement file");
+        camt::write_statements(&history.lock().unwrap(), &statement_options, &mut writer)
+            .and_then(|_| writer.finish())
+            .expect("Could not write statements");
+    }
+
+    #[cfg(feature = "columnar")]
+    if let (Some(dir), Some(history)) = (&options.columnar, &history) {
+        columnar::write_tables(
+            dir,
+            options.columnar_format,
+            engine.storage(),
+            &history.lock().unwrap(),
+        )
+        .unwrap_or_else(|e| panic!("Could not write columnar files: {}", e));
+    }
+
+    // compressed when the file name ends in .gz, .zst or .bz2
+    let mut file =
+        output_path.map(|path| compression::create(path).expect("Could not create output file"));
+    let mut stdout = std::io::stdout().lock();
+    let writer: &mut dyn std::io::Write = match &mut file {
+        Some(file) => file,
+        None => &mut stdout,
+    };
+    let written = match &history {
+        Some(history) if options.statement => statement::write_statement(
+            &history.lock().unwrap(),
+            options.client,
+            options.statement_format,
+            precision,
+            writer,
+        ),
+        _ => output::write_accounts_with(engine.storage(), format, precision, writer),
+    };
+    written
+        .and_then(|_| file.map_or(Ok(()), compression::Writer::finish))
+        .expect("Could not write output");
 }
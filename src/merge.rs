@@ -0,0 +1,291 @@
+use std::{fmt::Display, io, path::Path, str::FromStr};
+
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+
+use crate::{
+    id::TxId,
+    input::{Input, InputError, InputReader, Inputs},
+    timestamp::Timestamp,
+};
+
+/// Expands the arguments to the input files to read, in the order they are given. A directory
+/// stands for every file directly in it and a pattern like `in/*.csv` for every file it matches,
+/// both sorted by name so the order is the same on every run
+pub fn expand(args: &[String]) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for arg in args {
+        if Path::new(arg).is_dir() {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(arg)? {
+                let path = entry?.path();
+                if path.is_file() {
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            }
+            files.sort();
+            paths.extend(files);
+        } else if arg.contains(['*', '?', '[']) {
+            let pattern = glob::glob(arg)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            let mut files = Vec::new();
+            for path in pattern {
+                let path = path.map_err(io::Error::from)?;
+                if path.is_file() {
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            }
+            files.sort();
+            paths.extend(files);
+        } else {
+            paths.push(arg.clone());
+        }
+    }
+    Ok(paths)
+}
+
+/// The order inputs from several files are applied in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// Every input of the first file, then every input of the second, and so on
+    Sequential,
+    /// Interleaved by timestamp, every file is expected to be ordered by timestamp already
+    Timestamp,
+    /// Interleaved by tx id, every file is expected to be ordered by tx id already
+    Tx,
+}
+
+impl FromStr for MergeOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(MergeOrder::Sequential),
+            "timestamp" => Ok(MergeOrder::Timestamp),
+            "tx" => Ok(MergeOrder::Tx),
+            other => Err(format!("unknown merge order: {}", other)),
+        }
+    }
+}
+
+/// What happened to the lines of a single input file
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    /// lines read, including the malformed ones
+    pub read: u64,
+    /// lines that could not be read as an input at all
+    pub malformed: u64,
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+impl Display for FileStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} read, {} applied, {} rejected, {} malformed",
+            self.read, self.applied, self.rejected, self.malformed
+        )
+    }
+}
+
+/// Reads the inputs of several files as one, in a deterministic order. Every input comes with the
+/// index of the file it was read from
+pub struct Merger<'a> {
+    order: MergeOrder,
+    /// a file is read up to its first input later than this
+    as_of: Option<Timestamp>,
+    inputs: Vec<Inputs<'a>>,
+    /// the next input of every file, read ahead when merging
+    heads: Vec<Option<Result<Input, InputError>>>,
+    /// files that have nothing left
+    done: Vec<bool>,
+    /// the file being read when reading sequentially
+    current: usize,
+}
+
+impl<'a> Merger<'a> {
+    pub async fn new<R: AsyncRead + Unpin + Send + 'a>(
+        readers: Vec<InputReader<R>>,
+        order: MergeOrder,
+    ) -> Result<Merger<'a>, InputError> {
+        let mut inputs = Vec::new();
+        for reader in readers {
+            inputs.push(reader.inputs().await?);
+        }
+        Ok(Merger {
+            order,
+            as_of: None,
+            heads: inputs.iter().map(|_| None).collect(),
+            done: vec![false; inputs.len()],
+            inputs,
+            current: 0,
+        })
+    }
+
+    /// Stops reading every file at its first input later than `as_of`. Each file is expected to
+    /// be ordered by timestamp, so the rest of that file is later still, while the other files
+    /// are read on up to their own cutoff
+    pub fn with_as_of(mut self, as_of: Option<Timestamp>) -> Self {
+        self.as_of = as_of;
+        self
+    }
+
+    /// The next input and the index of its file, None when every file is read
+    pub async fn next(&mut self) -> Option<(usize, Result<Input, InputError>)> {
+        let as_of = self.as_of;
+        if self.order == MergeOrder::Sequential {
+            while self.current < self.inputs.len() {
+                match self.inputs[self.current].next().await {
+                    Some(input) if !is_late(&input, as_of) => return Some((self.current, input)),
+                    _ => self.current += 1,
+                }
+            }
+            return None;
+        }
+
+        for (i, head) in self.heads.iter_mut().enumerate() {
+            if head.is_none() && !self.done[i] {
+                *head = self.inputs[i]
+                    .next()
+                    .await
+                    .filter(|input| !is_late(input, as_of));
+                self.done[i] = head.is_none();
+            }
+        }
+        // Ties, and inputs without a timestamp, go to the file given first
+        let order = self.order;
+        let next = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((key(head.as_ref()?, order), i)))
+            .min()?
+            .1;
+        self.heads[next].take().map(|input| (next, input))
+    }
+}
+
+fn is_late(input: &Result<Input, InputError>, as_of: Option<Timestamp>) -> bool {
+    match (input, as_of) {
+        (Ok(input), Some(as_of)) => input.timestamp().is_some_and(|t| t > as_of),
+        _ => false,
+    }
+}
+
+/// The value an input is ordered by, always of the same kind within a merge
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Timestamp(Timestamp),
+    Tx(TxId),
+}
+
+/// What inputs are merged by, lines that could not be read and inputs without a timestamp are
+/// passed on as soon as they come up
+fn key(input: &Result<Input, InputError>, order: MergeOrder) -> Option<Key> {
+    let input = input.as_ref().ok()?;
+    match order {
+        MergeOrder::Timestamp => input.timestamp().map(Key::Timestamp),
+        MergeOrder::Tx => Some(Key::Tx(input.tx())),
+        MergeOrder::Sequential => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputOptions;
+
+    async fn merged(order: MergeOrder) -> Vec<(usize, u64)> {
+        merged_as_of(order, None).await
+    }
+
+    async fn merged_as_of(order: MergeOrder, as_of: Option<Timestamp>) -> Vec<(usize, u64)> {
+        let files = [
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,1.0,1000\n\
+             deposit,1,4,1.0,4000\n\
+             deposit,1,5,1.0,4000\n",
+            "type,client,tx,amount,timestamp\n\
+             deposit,2,2,1.0,2000\n\
+             nonsense\n\
+             deposit,2,3,1.0,4000\n",
+        ];
+        let readers: Vec<_> = files
+            .iter()
+            .map(|file| InputReader::from_reader(file.as_bytes(), InputOptions::default()))
+            .collect();
+        let mut merger = Merger::new(readers, order).await.unwrap().with_as_of(as_of);
+
+        let mut out = Vec::new();
+        while let Some((file, input)) = merger.next().await {
+            let tx = match input.map(|input| input.tx()) {
+                Ok(TxId::Number(tx)) => tx,
+                _ => 0,
+            };
+            out.push((file, tx));
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn merge_orders() {
+        assert_eq!(
+            vec![(0, 1), (0, 4), (0, 5), (1, 2), (1, 0), (1, 3)],
+            merged(MergeOrder::Sequential).await
+        );
+        // the malformed line goes as soon as it is at the head, and ties go to the first file
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 0), (0, 4), (0, 5), (1, 3)],
+            merged(MergeOrder::Timestamp).await
+        );
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 0), (1, 3), (0, 4), (0, 5)],
+            merged(MergeOrder::Tx).await
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_stops_each_file_at_as_of() {
+        // the late input in the first file does not stop the second file
+        let as_of = Some(Timestamp::from_millis(3000));
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 0)],
+            merged_as_of(MergeOrder::Sequential, as_of).await
+        );
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 0)],
+            merged_as_of(MergeOrder::Timestamp, as_of).await
+        );
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 0)],
+            merged_as_of(MergeOrder::Tx, as_of).await
+        );
+    }
+
+    #[tokio::test]
+    async fn expand_directories_and_patterns() {
+        let dir = std::env::temp_dir().join("toypa_expand");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["b.csv", "a.csv", "c.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir = dir.to_str().unwrap();
+
+        let paths = expand(&[
+            format!("{}/*.csv", dir),
+            dir.to_string(),
+            "other.csv".to_string(),
+        ])
+        .unwrap();
+        let names: Vec<&str> = paths
+            .iter()
+            .map(|p| p.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(
+            vec!["a.csv", "b.csv", "a.csv", "b.csv", "c.txt", "other.csv"],
+            names
+        );
+    }
+}
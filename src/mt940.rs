@@ -0,0 +1,306 @@
+use std::fmt::Display;
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::{bank::Entry, timestamp::Timestamp, FixedPoint};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Mt940Error {
+    /// A line is not a valid tag, or the value of a tag is not as the format says
+    Malformed {
+        line: usize,
+        tag: String,
+        reason: String,
+    },
+    /// A statement line comes before the `:25:` tag naming the account it is for
+    MissingAccount { line: usize },
+}
+
+impl Display for Mt940Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mt940Error::Malformed { line, tag, reason } => {
+                write!(f, "line {}: malformed :{}: tag, {}", line, tag, reason)
+            }
+            Mt940Error::MissingAccount { line } => {
+                write!(
+                    f,
+                    "line {}: statement line without a :25: account before it",
+                    line
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Mt940Error {}
+
+/// A tag with its value, continuation lines included
+struct Field<'a> {
+    line: usize,
+    tag: &'a str,
+    value: String,
+}
+
+/// Splits the message into its tags, leaving out the `{1:...}` blocks around it and the `-` ending
+/// it
+fn fields(text: &str) -> Result<Vec<Field<'_>>, Mt940Error> {
+    let mut fields: Vec<Field> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let mut line = line.trim_end();
+        if line.starts_with('{') {
+            // the basic and application headers, the text block starts after `{4:`
+            match line.find("{4:") {
+                Some(start) => line = &line[start + 3..],
+                None => continue,
+            }
+        }
+        if line.is_empty() || line == "-" || line == "-}" {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix(':') {
+            let malformed = |reason: &str| Mt940Error::Malformed {
+                line: number,
+                tag: rest.split(':').next().unwrap_or_default().to_string(),
+                reason: reason.to_string(),
+            };
+            let (tag, value) = rest.split_once(':').ok_or_else(|| malformed("no value"))?;
+            let valid = matches!(tag.as_bytes(), [a, b] | [a, b, b'A'..=b'Z']
+                if a.is_ascii_digit() && b.is_ascii_digit());
+            if !valid {
+                return Err(malformed("not a tag"));
+            }
+            fields.push(Field {
+                line: number,
+                tag,
+                value: value.to_string(),
+            });
+        } else {
+            let field = fields.last_mut().ok_or_else(|| Mt940Error::Malformed {
+                line: number,
+                tag: String::new(),
+                reason: "text before the first tag".to_string(),
+            })?;
+            field.value.push('\n');
+            field.value.push_str(line);
+        }
+    }
+    Ok(fields)
+}
+
+/// Reads the entries of MT940 customer statements.
+///
+/// Every `:61:` statement line becomes an entry of the account in the `:25:` tag before it, booked
+/// on its entry date, or else on its value date. The customer reference identifies the payment,
+/// the reference of the bank is used when it is `NONREF`. The reversal marks `RC` and `RD` undo the
+/// credit or debit with the same reference. Other tags are not read
+pub fn parse(text: &str) -> Result<Vec<Entry>, Mt940Error> {
+    let mut account = None;
+    let mut entries = Vec::new();
+    for field in fields(text)? {
+        match field.tag {
+            // a new statement, which names its own account
+            "20" => account = None,
+            "25" => account = Some(field.value.trim().to_string()),
+            "61" => {
+                let account = account
+                    .clone()
+                    .ok_or(Mt940Error::MissingAccount { line: field.line })?;
+                let entry = statement_line(&field.value, account).map_err(|reason| {
+                    Mt940Error::Malformed {
+                        line: field.line,
+                        tag: field.tag.to_string(),
+                        reason: reason.to_string(),
+                    }
+                })?;
+                entries.push(entry);
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a `:61:` statement line, which is laid out as
+/// `YYMMDD[MMDD](C|D|RC|RD)[funds code]amount(N|F|S)xxx reference[//bank reference]`
+fn statement_line(value: &str, account: String) -> Result<Entry, &'static str> {
+    let first = value.lines().next().unwrap_or_default();
+    let mut rest = first;
+    let value_date = take(&mut rest, 6)
+        .and_then(date)
+        .ok_or("invalid value date")?;
+    let mut booked = value_date;
+    if rest.starts_with(|c: char| c.is_ascii_digit()) {
+        booked = take(&mut rest, 4)
+            .and_then(|mmdd| entry_date(mmdd, value_date))
+            .ok_or("invalid entry date")?;
+    }
+
+    let (credit, reversal) = if let Some(r) = rest.strip_prefix("RC") {
+        rest = r;
+        (false, true)
+    } else if let Some(r) = rest.strip_prefix("RD") {
+        rest = r;
+        (true, true)
+    } else if let Some(r) = rest.strip_prefix('C') {
+        rest = r;
+        (true, false)
+    } else if let Some(r) = rest.strip_prefix('D') {
+        rest = r;
+        (false, false)
+    } else {
+        return Err("no debit or credit mark");
+    };
+    // the funds code, the third letter of the currency code
+    if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        rest = &rest[1..];
+    }
+
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ',')
+        .unwrap_or(rest.len());
+    let (amount, after) = rest.split_at(end);
+    rest = after;
+    if amount.matches(',').count() != 1 || amount.starts_with(',') {
+        return Err("invalid amount");
+    }
+    let amount: FixedPoint = format!("{}0", amount.replace(',', "."))
+        .parse()
+        .map_err(|_| "invalid amount")?;
+
+    match rest.as_bytes() {
+        [b'N' | b'F' | b'S', _, _, _, ..] => rest = &rest[4..],
+        _ => return Err("invalid transaction type"),
+    }
+
+    let (customer, bank) = match rest.split_once("//") {
+        Some((customer, bank)) => (customer.trim(), bank.trim()),
+        None => (rest.trim(), ""),
+    };
+    let reference = match customer {
+        "" | "NONREF" => bank,
+        customer => customer,
+    };
+    if reference.is_empty() {
+        return Err("no reference");
+    }
+
+    Ok(Entry {
+        account,
+        amount,
+        credit,
+        reversal,
+        reference: reference.to_string(),
+        booked: Some(Timestamp::from_millis(
+            booked
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis(),
+        )),
+    })
+}
+
+/// The first n characters, taken off the rest
+fn take<'s>(rest: &mut &'s str, n: usize) -> Option<&'s str> {
+    let taken = rest.get(..n)?;
+    *rest = &rest[n..];
+    Some(taken)
+}
+
+/// A `YYMMDD` date, years are taken to be in this century
+fn date(yymmdd: &str) -> Option<NaiveDate> {
+    let number = |range: std::ops::Range<usize>| yymmdd.get(range)?.parse::<u32>().ok();
+    NaiveDate::from_ymd_opt(2000 + number(0..2)? as i32, number(2..4)?, number(4..6)?)
+}
+
+/// A `MMDD` entry date, in the year of the value date unless it falls across the new year
+fn entry_date(mmdd: &str, value_date: NaiveDate) -> Option<NaiveDate> {
+    let month = mmdd.get(0..2)?.parse::<u32>().ok()?;
+    let day = mmdd.get(2..4)?.parse::<u32>().ok()?;
+    let year = match (value_date.month(), month) {
+        (12, 1) => value_date.year() + 1,
+        (1, 12) => value_date.year() - 1,
+        _ => value_date.year(),
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::parse_datetime;
+
+    #[tokio::test]
+    async fn mt940_statement() {
+        let text = std::fs::read_to_string("testdata/mt940.sta").unwrap();
+        let entries = parse(&text).unwrap();
+
+        assert_eq!(5, entries.len());
+        assert!(entries[..4]
+            .iter()
+            .all(|entry| entry.account == "NL91ABNA0417164300"));
+        assert_eq!("DE89370400440532013000", entries[4].account);
+
+        assert!(entries[0].credit);
+        assert!(!entries[0].reversal);
+        assert_eq!("E2E-0001", entries[0].reference);
+        assert_eq!("150.25".parse(), Ok(entries[0].amount));
+        assert_eq!(parse_datetime("2023-01-05"), entries[0].booked);
+
+        // NONREF, the reference of the bank is used instead, booked a day after the value date
+        assert!(!entries[1].credit);
+        assert_eq!("SVC-0002", entries[1].reference);
+        assert_eq!(parse_datetime("2023-01-07"), entries[1].booked);
+
+        // reversal of the credit
+        assert!(!entries[2].credit);
+        assert!(entries[2].reversal);
+        assert_eq!("E2E-0001", entries[2].reference);
+
+        // reversal of the debit
+        assert!(entries[3].credit);
+        assert!(entries[3].reversal);
+        assert_eq!("SVC-0002", entries[3].reference);
+
+        // the entry date falls in the next year
+        assert_eq!("75".parse(), Ok(entries[4].amount));
+        assert_eq!(parse_datetime("2023-01-02"), entries[4].booked);
+    }
+
+    #[tokio::test]
+    async fn mt940_errors() {
+        let error = |line: &str| {
+            parse(&format!(":20:REF\n:25:NL91ABNA0417164300\n{}\n", line))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            "line 3: malformed :61: tag, invalid value date",
+            error(":61:231305C1,00NTRFREF")
+        );
+        assert_eq!(
+            "line 3: malformed :61: tag, no debit or credit mark",
+            error(":61:230105X1,00NTRFREF")
+        );
+        assert_eq!(
+            "line 3: malformed :61: tag, invalid amount",
+            error(":61:230105C100NTRFREF")
+        );
+        assert_eq!(
+            "line 3: malformed :61: tag, invalid transaction type",
+            error(":61:230105C1,00XTRFREF")
+        );
+        assert_eq!(
+            "line 3: malformed :61: tag, no reference",
+            error(":61:230105C1,00NTRFNONREF")
+        );
+        assert_eq!("line 3: malformed :6X: tag, not a tag", error(":6X:1"));
+        assert_eq!(
+            "line 1: statement line without a :25: account before it",
+            parse(":61:230105C1,00NTRFREF").unwrap_err().to_string()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{
+    accounts::{DisputeState, TransactionError},
+    engine::Outcome,
+    id::{ClientId, TxId},
+    input::Input,
+};
+
+/// Hooks called by the [`Engine`](crate::Engine) as it changes the state of accounts.
+///
+/// Every method does nothing by default, so an observer only implements what it cares about.
+/// The hooks are called after the change has been made, in the order the observers were added
+pub trait Observer {
+    /// A transaction was applied to the account of its client
+    fn on_applied(&mut self, _input: &Input, _outcome: &Outcome) {}
+
+    /// A transaction was rejected. No funds moved, but a client without an account still gets an
+    /// empty one, as it does for every transaction
+    fn on_rejected(&mut self, _input: &Input, _error: &TransactionError) {}
+
+    /// The account of the client was locked, after this every transaction for it is rejected
+    fn on_locked(&mut self, _client: ClientId) {}
+
+    /// A dispute was opened on transaction `tx`, and its funds are held
+    fn on_dispute_opened(&mut self, _client: ClientId, _tx: TxId) {}
+
+    /// The dispute on transaction `tx` ended, either resolved or charged back
+    fn on_dispute_closed(&mut self, _client: ClientId, _tx: TxId, _state: DisputeState) {}
+}
@@ -1,42 +1,319 @@
-use crate::accounts;
+use std::{fmt::Display, io, ops::Add, str::FromStr};
 
+use serde::{Serialize, Serializer};
+
+use crate::{
+    accounts::{self, Account},
+    id::ClientId,
+    simple_fp::DECIMALS,
+    FixedPoint, Rounding,
+};
+
+#[derive(Serialize, Clone)]
 pub struct Output {
     /// The client the text is regarding
-    client: u16,
+    client: ClientId,
     /// the amount available for usage, should equal total - held
-    available: f64,
+    available: Amount,
     /// the amount locked during a dispute, should be the total - available
-    held: f64,
+    held: Amount,
     /// the total amount of funds
-    total: f64,
+    total: Amount,
     /// if the account is currently locked due to an ongoing chargeback
     locked: bool,
+    /// how much of the credit line is in use, meaning how far below zero available is
+    credit_used: Amount,
 }
 
-impl Output {
-    pub fn csv_line(&self) -> String {
-        format!(
-            "{}, {:0.4}, {:0.4}, {:0.4}, {}",
-            self.client, self.available, self.held, self.total, self.locked
-        )
+/// How many decimals amounts are written with, and how they are rounded to get there
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Precision {
+    /// at most 4, which writes amounts exactly
+    pub decimals: usize,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            decimals: DECIMALS,
+            rounding: Rounding::HalfEven,
+        }
+    }
+}
+
+/// An amount already rounded to the precision it is written with
+#[derive(Copy, Clone)]
+pub(crate) struct Amount {
+    value: FixedPoint,
+    decimals: usize,
+}
+
+impl Amount {
+    pub(crate) fn new(value: FixedPoint, precision: Precision) -> Self {
+        Self {
+            // only an amount close to the limits of FixedPoint can overflow, it is kept as it is
+            value: value
+                .round_to(precision.decimals, precision.rounding)
+                .unwrap_or(value),
+            decimals: precision.decimals,
+        }
     }
 }
 
-pub fn print_from_accounts(accountstore: accounts::AccountStorage) -> () {
-    // using csv writer for this, just seems uneccesary...
-    // especially since no formatting rules are really in effect
+/// The sum of two amounts of the same precision, so a printed sum always adds up to its parts
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount {
+            value: self.value + other.value,
+            decimals: self.decimals,
+        }
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // padded, so amounts line up in columns
+        f.pad(&self.value.formatted().decimals(self.decimals).to_string())
+    }
+}
+
+/// Amounts are written as decimal strings, so no precision is lost on the way out
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
-    println!("client, available, held, total, locked");
+impl Output {
+    pub fn new(client: ClientId, account: &Account) -> Self {
+        Self::with_precision(client, account, Precision::default())
+    }
 
-    for (client, account) in accountstore.accounts() {
-        let out = Output {
-            client: *client,
-            available: account.available().to_f64(),
-            held: account.held().to_f64(),
-            total: account.total().to_f64(),
+    /// Creates the output with every amount rounded to the precision. The total is the sum of the
+    /// rounded available and held funds, so the printed amounts add up
+    pub fn with_precision(client: ClientId, account: &Account, precision: Precision) -> Self {
+        let available = Amount::new(account.available(), precision);
+        let held = Amount::new(account.held(), precision);
+        Self {
+            client,
+            available,
+            held,
+            total: available + held,
             locked: account.locked(),
+            credit_used: Amount::new(account.credit_used(), precision),
+        }
+    }
+}
+
+/// The formats the account balances can be written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// A csv file with a header
+    Csv,
+    /// A single pretty printed json array
+    Json,
+    /// One json object per line
+    JsonLines,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" | "jsonlines" => Ok(Format::JsonLines),
+            other => Err(format!("unknown output format: {}", other)),
+        }
+    }
+}
+
+/// Writes one output line at a time in some format
+pub trait Formatter {
+    fn write(&mut self, output: &Output) -> io::Result<()>;
+
+    /// Writes whatever is needed to end the output, and flushes it
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct CsvFormatter<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: io::Write> Formatter for CsvFormatter<W> {
+    fn write(&mut self, output: &Output) -> io::Result<()> {
+        self.writer.serialize(output).map_err(io::Error::from)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+struct JsonFormatter<W: io::Write> {
+    writer: W,
+    /// everything is kept until the end, to write it as a single pretty printed array
+    outputs: Vec<Output>,
+}
+
+impl<W: io::Write> Formatter for JsonFormatter<W> {
+    fn write(&mut self, output: &Output) -> io::Result<()> {
+        self.outputs.push(output.clone());
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut self.writer, &self.outputs)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+struct JsonLinesFormatter<W: io::Write> {
+    writer: W,
+}
+
+impl<W: io::Write> Formatter for JsonLinesFormatter<W> {
+    fn write(&mut self, output: &Output) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, output)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Creates a formatter writing `format` to the writer
+pub fn formatter<'w, W: io::Write + 'w>(format: Format, writer: W) -> Box<dyn Formatter + 'w> {
+    match format {
+        Format::Csv => Box::new(CsvFormatter {
+            writer: csv::Writer::from_writer(writer),
+        }),
+        Format::Json => Box::new(JsonFormatter {
+            writer,
+            outputs: Vec::new(),
+        }),
+        Format::JsonLines => Box::new(JsonLinesFormatter { writer }),
+    }
+}
+
+/// Writes the balances of every account in the given format
+pub fn write_accounts<W: io::Write>(
+    accountstore: &accounts::AccountStorage,
+    format: Format,
+    writer: W,
+) -> io::Result<()> {
+    write_accounts_with(accountstore, format, Precision::default(), writer)
+}
+
+/// Writes the balances of every account in the given format, rounded to the precision
+pub fn write_accounts_with<W: io::Write>(
+    accountstore: &accounts::AccountStorage,
+    format: Format,
+    precision: Precision,
+    writer: W,
+) -> io::Result<()> {
+    let mut formatter = formatter(format, writer);
+    let mut written = Ok(());
+    accountstore
+        .accounts()
+        .for_each(&mut |client, account| {
+            if written.is_ok() {
+                written = formatter.write(&Output::with_precision(client, account, precision));
+            }
+        })
+        .map_err(io::Error::other)?;
+    written?;
+    formatter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Input, TransactionType};
+
+    fn written(format: Format) -> String {
+        let mut accounts = accounts::AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 2, 1, Some(5.1234)));
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 2, Some(1.5)));
+        let _res = accounts.apply(Input::new(TransactionType::Dispute, 1, 2, None));
+
+        let mut out = Vec::new();
+        write_accounts(&accounts, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn output_csv() {
+        assert_eq!(
+            "client,available,held,total,locked,credit_used\n\
+             1,0.0000,1.5000,1.5000,false,0.0000\n\
+             2,5.1234,0.0000,5.1234,false,0.0000\n",
+            written(Format::Csv)
+        );
+    }
+
+    #[tokio::test]
+    async fn output_precision() {
+        let mut accounts = accounts::AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 1, Some(5.125)));
+
+        let precision = Precision {
+            decimals: 2,
+            rounding: Rounding::HalfEven,
+        };
+        let mut out = Vec::new();
+        write_accounts_with(&accounts, Format::Csv, precision, &mut out).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked,credit_used\n\
+             1,5.12,0.00,5.12,false,0.00\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn output_total_adds_up() {
+        let mut accounts = accounts::AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 1, Some(0.005)));
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 2, Some(0.005)));
+        let _res = accounts.apply(Input::new(TransactionType::Dispute, 1, 2, None));
+
+        // the exact total of 0.01 would not match the rounded available and held funds
+        let precision = Precision {
+            decimals: 2,
+            rounding: Rounding::HalfEven,
         };
-        let s = out.csv_line();
-        println!("{}", s);
+        let mut out = Vec::new();
+        write_accounts_with(&accounts, Format::Csv, precision, &mut out).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked,credit_used\n\
+             1,0.00,0.00,0.00,false,0.00\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn output_json_lines() {
+        let out = written(Format::JsonLines);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            r#"{"client":1,"available":"0.0000","held":"1.5000","total":"1.5000","locked":false,"credit_used":"0.0000"}"#,
+            lines[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn output_json() {
+        let parsed: serde_json::Value = serde_json::from_str(&written(Format::Json)).unwrap();
+        let accounts = parsed.as_array().unwrap();
+        assert_eq!(2, accounts.len());
+        assert_eq!("5.1234", accounts[1]["available"]);
+        assert_eq!(1, accounts[0]["client"]);
     }
 }
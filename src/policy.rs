@@ -0,0 +1,348 @@
+use serde::Deserialize;
+
+use crate::{input::TransactionType, timestamp::MILLIS_PER_DAY};
+
+/// The rules an [`Account`](crate::Account) consults whenever it has to decide whether a
+/// transaction is allowed, so different deployments can run the same engine with different rules.
+///
+/// Every method defaults to the behavior of [`DefaultPolicy`]
+pub trait Policy: Send + Sync {
+    /// Whether transactions of this type can be disputed at all
+    fn disputable(&self, r#type: TransactionType) -> bool {
+        r#type == TransactionType::Deposit
+    }
+
+    /// Whether a dispute may use the credit line to hold funds that are already spent
+    fn dispute_uses_credit(&self) -> bool {
+        true
+    }
+
+    /// Whether a dispute is opened even when the funds to hold are not there, letting the
+    /// available funds go below the credit limit
+    fn dispute_without_funds(&self) -> bool {
+        false
+    }
+
+    /// Whether a chargeback locks the account
+    fn lock_on_chargeback(&self) -> bool {
+        true
+    }
+
+    /// Whether transactions of this type are still applied to a locked account
+    fn allowed_when_locked(&self, _type: TransactionType) -> bool {
+        false
+    }
+
+    /// Whether funds can be withdrawn while the account has a dispute that is not closed yet
+    fn withdraw_during_dispute(&self) -> bool {
+        true
+    }
+
+    /// How old a transaction may be, in milliseconds, to still be disputable
+    fn dispute_window(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// Only deposits can be disputed, the funds must be there to hold (counting the credit line),
+/// and a chargeback locks the account for good
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultPolicy;
+
+impl Policy for DefaultPolicy {}
+
+/// Deposits and withdrawals can be disputed whether or not the funds are there, and chargebacks
+/// do not lock the account
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LenientPolicy;
+
+impl Policy for LenientPolicy {
+    fn disputable(&self, r#type: TransactionType) -> bool {
+        matches!(
+            r#type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        )
+    }
+
+    fn dispute_without_funds(&self) -> bool {
+        true
+    }
+
+    fn lock_on_chargeback(&self) -> bool {
+        false
+    }
+}
+
+/// Disputed funds must be covered by the client's own funds, and nothing can be withdrawn while
+/// a dispute is open
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StrictPolicy;
+
+impl Policy for StrictPolicy {
+    fn dispute_uses_credit(&self) -> bool {
+        false
+    }
+
+    fn withdraw_during_dispute(&self) -> bool {
+        false
+    }
+}
+
+/// Card payments can be charged back within a fixed window whether or not the client still has
+/// the funds, and disputes that are open when the account gets locked are still settled
+#[derive(Debug, Clone, Copy)]
+pub struct CardSchemePolicy {
+    /// dispute window in milliseconds
+    pub dispute_window: i64,
+}
+
+impl Default for CardSchemePolicy {
+    fn default() -> Self {
+        Self {
+            dispute_window: 120 * MILLIS_PER_DAY,
+        }
+    }
+}
+
+impl Policy for CardSchemePolicy {
+    fn dispute_without_funds(&self) -> bool {
+        true
+    }
+
+    fn allowed_when_locked(&self, r#type: TransactionType) -> bool {
+        matches!(
+            r#type,
+            TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+
+    fn dispute_window(&self) -> Option<i64> {
+        Some(self.dispute_window)
+    }
+}
+
+/// What a locked account still accepts, overriding the policy
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccounts {
+    /// Every transaction is rejected
+    Reject,
+    /// Disputes that were open when the account got locked can still be resolved or charged back
+    SettleDisputes,
+}
+
+/// Wraps a policy, replacing only what it allows on locked accounts
+pub struct WithLockedAccounts {
+    pub policy: Box<dyn Policy>,
+    pub locked: LockedAccounts,
+}
+
+impl Policy for WithLockedAccounts {
+    fn disputable(&self, r#type: TransactionType) -> bool {
+        self.policy.disputable(r#type)
+    }
+
+    fn dispute_uses_credit(&self) -> bool {
+        self.policy.dispute_uses_credit()
+    }
+
+    fn dispute_without_funds(&self) -> bool {
+        self.policy.dispute_without_funds()
+    }
+
+    fn lock_on_chargeback(&self) -> bool {
+        self.policy.lock_on_chargeback()
+    }
+
+    fn allowed_when_locked(&self, r#type: TransactionType) -> bool {
+        match self.locked {
+            LockedAccounts::Reject => false,
+            LockedAccounts::SettleDisputes => matches!(
+                r#type,
+                TransactionType::Resolve | TransactionType::Chargeback
+            ),
+        }
+    }
+
+    fn withdraw_during_dispute(&self) -> bool {
+        self.policy.withdraw_during_dispute()
+    }
+
+    fn dispute_window(&self) -> Option<i64> {
+        self.policy.dispute_window()
+    }
+}
+
+/// Wraps a policy, replacing only its dispute window. The window is read whenever a dispute comes
+/// in, so it holds for accounts created before it was set as well
+pub struct WithDisputeWindow {
+    pub policy: Box<dyn Policy>,
+    /// dispute window in milliseconds
+    pub dispute_window: i64,
+}
+
+impl Policy for WithDisputeWindow {
+    fn disputable(&self, r#type: TransactionType) -> bool {
+        self.policy.disputable(r#type)
+    }
+
+    fn dispute_uses_credit(&self) -> bool {
+        self.policy.dispute_uses_credit()
+    }
+
+    fn dispute_without_funds(&self) -> bool {
+        self.policy.dispute_without_funds()
+    }
+
+    fn lock_on_chargeback(&self) -> bool {
+        self.policy.lock_on_chargeback()
+    }
+
+    fn allowed_when_locked(&self, r#type: TransactionType) -> bool {
+        self.policy.allowed_when_locked(r#type)
+    }
+
+    fn withdraw_during_dispute(&self) -> bool {
+        self.policy.withdraw_during_dispute()
+    }
+
+    fn dispute_window(&self) -> Option<i64> {
+        Some(self.dispute_window)
+    }
+}
+
+/// The names of the built in policies
+pub const NAMES: [&str; 4] = ["default", "lenient", "strict", "card-scheme"];
+
+/// Looks up one of the built in policies by name, `default`, `lenient`, `strict` or `card-scheme`
+pub fn by_name(name: &str) -> Option<Box<dyn Policy>> {
+    match name {
+        "default" => Some(Box::new(DefaultPolicy)),
+        "lenient" => Some(Box::new(LenientPolicy)),
+        "strict" => Some(Box::new(StrictPolicy)),
+        "card-scheme" => Some(Box::new(CardSchemePolicy::default())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accounts::{Account, TransactionError},
+        input::Input,
+        timestamp::Timestamp,
+        FixedPoint,
+    };
+
+    #[tokio::test]
+    async fn lenient_policy() {
+        let policy = LenientPolicy;
+        let mut account = Account::new();
+
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+            Input::new(TransactionType::Withdrawal, 1, 2, Some(40.0)),
+            // the deposit is mostly spent, but can still be disputed
+            Input::new(TransactionType::Dispute, 1, 1, None),
+        ];
+        for input in inputs {
+            assert!(account.handle_transaction_with(input, &policy).is_ok());
+        }
+        assert_eq!(-40.0, account.available());
+        assert_eq!(50.0, account.held());
+
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 1, None);
+        assert!(account.handle_transaction_with(chargeback, &policy).is_ok());
+        assert!(!account.locked());
+
+        // the client claims the withdrawal was not theirs, and gets it back on a chargeback
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None);
+        assert!(account.handle_transaction_with(dispute, &policy).is_ok());
+        assert_eq!(40.0, account.held());
+        let chargeback = Input::new(TransactionType::Chargeback, 1, 2, None);
+        assert!(account.handle_transaction_with(chargeback, &policy).is_ok());
+        assert_eq!(0.0, account.available());
+        assert_eq!(0.0, account.held());
+    }
+
+    #[tokio::test]
+    async fn strict_policy() {
+        let policy = StrictPolicy;
+        let mut account = Account::new();
+        account.set_credit_limit(FixedPoint::from_f64(100.0));
+
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(10.0)),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(20.0)),
+        ];
+        for input in inputs {
+            assert!(account.handle_transaction_with(input, &policy).is_ok());
+        }
+
+        // the default policy would let the credit line cover this, the strict does not
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        assert!(matches!(
+            account.handle_transaction_with(dispute, &policy),
+            Err(TransactionError::NotEnoughAvailableFunds)
+        ));
+
+        let dispute = Input::new(TransactionType::Dispute, 1, 2, None);
+        assert!(account.handle_transaction_with(dispute, &policy).is_ok());
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 4, Some(1.0));
+        assert!(matches!(
+            account.handle_transaction_with(withdrawal, &policy),
+            Err(TransactionError::DisputeInProgress)
+        ));
+        assert_eq!(30.0, account.available());
+    }
+
+    #[tokio::test]
+    async fn card_scheme_policy() {
+        let policy = CardSchemePolicy::default();
+        let mut account = Account::new();
+        let day = |n: i64| Timestamp::from_millis(n * MILLIS_PER_DAY);
+
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)).with_timestamp(day(0)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(10.0)).with_timestamp(day(10)),
+            Input::new(TransactionType::Deposit, 1, 3, Some(20.0)).with_timestamp(day(20)),
+            Input::new(TransactionType::Dispute, 1, 2, None).with_timestamp(day(30)),
+            Input::new(TransactionType::Dispute, 1, 3, None).with_timestamp(day(30)),
+            Input::new(TransactionType::Chargeback, 1, 2, None).with_timestamp(day(40)),
+        ];
+        for input in inputs {
+            assert!(account.handle_transaction_with(input, &policy).is_ok());
+        }
+        assert!(account.locked());
+        // only the funds of the dispute still open are held
+        assert_eq!(20.0, account.held());
+
+        // the open dispute is still settled on the locked account, but nothing else is
+        let deposit = Input::new(TransactionType::Deposit, 1, 4, Some(1.0)).with_timestamp(day(50));
+        assert!(account.handle_transaction_with(deposit, &policy).is_err());
+        let resolve = Input::new(TransactionType::Resolve, 1, 3, None).with_timestamp(day(50));
+        assert!(account.handle_transaction_with(resolve, &policy).is_ok());
+        assert_eq!(70.0, account.available());
+        assert_eq!(0.0, account.held());
+
+        // and deposits are too old to dispute after 120 days
+        let mut account = Account::new();
+        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(5.0)).with_timestamp(day(0));
+        assert!(account.handle_transaction_with(deposit, &policy).is_ok());
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None).with_timestamp(day(121));
+        assert!(matches!(
+            account.handle_transaction_with(dispute, &policy),
+            Err(TransactionError::DisputeWindowExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn policies_by_name() {
+        for name in NAMES {
+            assert!(by_name(name).is_some());
+        }
+        assert!(by_name("anything").is_none());
+    }
+}
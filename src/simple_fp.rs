@@ -1,10 +1,22 @@
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::{
+    fmt::Display,
+    iter::Sum,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    str::FromStr,
+};
 
-#[derive(Debug, PartialOrd, Copy, Clone, PartialEq, Eq, Ord)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialOrd, Copy, Clone, PartialEq, Eq, Ord, Serialize, Deserialize, Default)]
 /// Fixed point implementation for numbers with a 4 decimals point, achieved by instead of storing
 /// Decimal numbers, only allow operations on integers representing TenThoushanth's
 pub struct FixedPoint(i128);
 
+/// The number of decimals kept
+pub const DECIMALS: usize = 4;
+/// 10^DECIMALS, the raw value of 1.0
+const SCALE: u128 = 10000;
+
 impl FixedPoint {
     // Yep, I did this, sue me (please dont)
     pub fn from_f64(n: f64) -> Self {
@@ -21,13 +33,170 @@ impl FixedPoint {
     }
     pub fn to_f32(self) -> f32 {
         let f = self.0 as f32;
-        let f = f / 10000.0;
-        f
+        f / 10000.0
     }
     pub fn to_f64(self) -> f64 {
         let f = self.0 as f64;
-        let f = f / 10000.0;
-        f
+        f / 10000.0
+    }
+    /// The raw value, in ten-thousandths, for formats with their own decimal type
+    pub fn to_raw(self) -> i128 {
+        self.0
+    }
+}
+
+/// Exact formatting with all 4 decimals, without going through any float
+impl Display for FixedPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.formatted().fmt(f)
+    }
+}
+
+impl FixedPoint {
+    /// Formatting with more options than the plain [`Display`], mostly for reports read by humans
+    pub fn formatted(self) -> Formatted {
+        Formatted {
+            value: self,
+            trim_zeros: false,
+            separator: None,
+            decimals: DECIMALS,
+        }
+    }
+}
+
+/// A [`FixedPoint`] with formatting options, created through [`FixedPoint::formatted`]
+#[derive(Debug, Copy, Clone)]
+pub struct Formatted {
+    value: FixedPoint,
+    trim_zeros: bool,
+    separator: Option<char>,
+    decimals: usize,
+}
+
+impl Formatted {
+    /// Remove trailing zeros from the decimals, and the decimal point if nothing is left
+    pub fn trim_zeros(mut self) -> Self {
+        self.trim_zeros = true;
+        self
+    }
+
+    /// Group the integer digits in thousands with the separator
+    pub fn thousands(mut self, separator: char) -> Self {
+        self.separator = Some(separator);
+        self
+    }
+
+    /// Only write the first `decimals` decimals, anything after them is cut off, so round the
+    /// value with [`FixedPoint::round_to`] first
+    pub fn decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals.min(DECIMALS);
+        self
+    }
+}
+
+impl Display for Formatted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // unsigned_abs, since the absolute value of i128::MIN does not fit in an i128
+        let abs = self.value.0.unsigned_abs();
+        if self.value.0 < 0 {
+            f.write_str("-")?;
+        }
+
+        let integer = (abs / SCALE).to_string();
+        match self.separator {
+            Some(separator) => {
+                for (i, digit) in integer.chars().enumerate() {
+                    if i > 0 && (integer.len() - i).is_multiple_of(3) {
+                        write!(f, "{}", separator)?;
+                    }
+                    write!(f, "{}", digit)?;
+                }
+            }
+            None => f.write_str(&integer)?,
+        }
+
+        let decimals = format!("{:0width$}", abs % SCALE, width = DECIMALS);
+        let decimals = &decimals[..self.decimals];
+        let decimals = if self.trim_zeros {
+            decimals.trim_end_matches('0')
+        } else {
+            decimals
+        };
+        if !decimals.is_empty() {
+            write!(f, ".{}", decimals)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseFixedPointError {
+    /// There were no digits to parse
+    Empty,
+    /// Something other than a sign, digits and a single decimal point was found
+    InvalidDigit,
+    /// More decimals than can be represented, which would lose precision
+    TooManyDecimals,
+    /// The number does not fit
+    Overflow,
+}
+
+impl Display for ParseFixedPointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ParseFixedPointError::Empty => "no digits to parse",
+            ParseFixedPointError::InvalidDigit => "invalid digit found",
+            ParseFixedPointError::TooManyDecimals => "more than 4 decimals",
+            ParseFixedPointError::Overflow => "number too large",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for ParseFixedPointError {}
+
+/// Exact parsing of decimal strings such as `-12.5`, `3` or `.25`, the inverse of [`Display`].
+/// Decimals beyond the 4th are only accepted if they are zeros
+impl FromStr for FixedPoint {
+    type Err = ParseFixedPointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (integer, decimals) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && decimals.is_empty() {
+            return Err(ParseFixedPointError::Empty);
+        }
+        if !integer
+            .bytes()
+            .chain(decimals.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseFixedPointError::InvalidDigit);
+        }
+        if decimals.len() > DECIMALS && decimals.bytes().skip(DECIMALS).any(|b| b != b'0') {
+            return Err(ParseFixedPointError::TooManyDecimals);
+        }
+
+        // Accumulate the magnitude as unsigned, which has room for the magnitude of i128::MIN
+        let mut abs: u128 = 0;
+        let padded = decimals.bytes().chain(std::iter::repeat(b'0'));
+        for digit in integer.bytes().chain(padded.take(DECIMALS)) {
+            abs = abs
+                .checked_mul(10)
+                .and_then(|v| v.checked_add((digit - b'0') as u128))
+                .ok_or(ParseFixedPointError::Overflow)?;
+        }
+
+        let value = if negative {
+            0i128.checked_sub_unsigned(abs)
+        } else {
+            i128::try_from(abs).ok()
+        };
+        value.map(Self).ok_or(ParseFixedPointError::Overflow)
     }
 }
 
@@ -79,6 +248,164 @@ impl Sub for FixedPoint {
     }
 }
 
+/// How to round when the exact result of an operation has more decimals than can be kept
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// Drop the extra decimals
+    TowardZero,
+    /// Round up in magnitude whenever anything is dropped
+    AwayFromZero,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceiling,
+    /// Round to the nearest, ties away from zero
+    HalfUp,
+    /// Round to the nearest, ties to the even neighbour, also known as bankers rounding
+    HalfEven,
+}
+
+/// Divides and rounds the quotient, None if the divisor is zero or the result overflows
+fn div_round(n: i128, d: i128, rounding: Rounding) -> Option<i128> {
+    let q = n.checked_div(d)?;
+    let r = n % d;
+    if r == 0 {
+        return Some(q);
+    }
+    // the exact result lies between q and q + step, where step points away from zero
+    let step = if (n < 0) != (d < 0) { -1 } else { 1 };
+    let (r, d) = (r.unsigned_abs(), d.unsigned_abs());
+    let away = match rounding {
+        Rounding::TowardZero => false,
+        Rounding::AwayFromZero => true,
+        Rounding::Floor => step < 0,
+        Rounding::Ceiling => step > 0,
+        Rounding::HalfUp => r >= d - r,
+        Rounding::HalfEven => r > d - r || (r == d - r && q % 2 != 0),
+    };
+    if away {
+        q.checked_add(step)
+    } else {
+        Some(q)
+    }
+}
+
+impl FixedPoint {
+    /// Multiplies, rounding the result to 4 decimals. None on overflow
+    pub fn checked_mul(self, rhs: Self, rounding: Rounding) -> Option<Self> {
+        let product = self.0.checked_mul(rhs.0)?;
+        div_round(product, SCALE as i128, rounding).map(Self)
+    }
+
+    /// Divides, rounding the result to 4 decimals. None when dividing by zero or on overflow
+    pub fn checked_div(self, rhs: Self, rounding: Rounding) -> Option<Self> {
+        let scaled = self.0.checked_mul(SCALE as i128)?;
+        div_round(scaled, rhs.0, rounding).map(Self)
+    }
+
+    /// Multiplies with `numerator / denominator`, only rounding once at the end, which makes it
+    /// the exact way of doing pro-rata and FX conversions given as a ratio of integers
+    pub fn mul_ratio(self, numerator: i128, denominator: i128, rounding: Rounding) -> Option<Self> {
+        let product = self.0.checked_mul(numerator)?;
+        div_round(product, denominator, rounding).map(Self)
+    }
+
+    /// `percent` percent of the amount, so a percent of 2.5 gives 2.5% of it
+    pub fn percentage(self, percent: Self, rounding: Rounding) -> Option<Self> {
+        let product = self.0.checked_mul(percent.0)?;
+        div_round(product, SCALE as i128 * 100, rounding).map(Self)
+    }
+
+    /// Rounds the amount to `decimals` decimals, which can be at most 4. None on overflow
+    pub fn round_to(self, decimals: usize, rounding: Rounding) -> Option<Self> {
+        let factor = 10i128.pow(DECIMALS.saturating_sub(decimals) as u32);
+        div_round(self.0, factor, rounding)?
+            .checked_mul(factor)
+            .map(Self)
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+
+    /// Splits the amount into parts proportional to the weights, which always sum up to exactly
+    /// the amount. What is left after rounding every part toward zero is handed out one unit at a
+    /// time, starting from the first part. None if all weights are zero
+    pub fn allocate(self, weights: &[u64]) -> Option<Vec<Self>> {
+        let total: i128 = weights.iter().map(|w| *w as i128).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut parts = weights
+            .iter()
+            .map(|w| self.mul_ratio(*w as i128, total, Rounding::TowardZero))
+            .collect::<Option<Vec<Self>>>()?;
+
+        let allocated: Self = parts.iter().sum();
+        let mut left = (self - allocated).0;
+        let unit = left.signum();
+        // every part lost less than one unit, so a single pass always hands out everything
+        for (part, weight) in parts.iter_mut().zip(weights) {
+            if left == 0 {
+                break;
+            }
+            if *weight > 0 {
+                part.0 += unit;
+                left -= unit;
+            }
+        }
+        Some(parts)
+    }
+
+    /// Splits the amount into `n` parts as equal as possible, that sum up to exactly the amount
+    pub fn split(self, n: usize) -> Option<Vec<Self>> {
+        self.allocate(&vec![1; n])
+    }
+}
+
+/// Rounds to nearest with ties to even, and panics on overflow just like the integer types,
+/// use [`FixedPoint::checked_mul`] to choose the rounding or handle the overflow
+impl Mul for FixedPoint {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.checked_mul(rhs, Rounding::HalfEven)
+            .expect("attempt to multiply with overflow")
+    }
+}
+
+/// Rounds to nearest with ties to even, and panics when dividing by zero or on overflow,
+/// use [`FixedPoint::checked_div`] to choose the rounding or handle the errors
+impl Div for FixedPoint {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        self.checked_div(rhs, Rounding::HalfEven)
+            .expect("attempt to divide by zero or with overflow")
+    }
+}
+
+impl Neg for FixedPoint {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+
+impl Sum for FixedPoint {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self(0), |sum, v| sum + v)
+    }
+}
+
+impl<'a> Sum<&'a FixedPoint> for FixedPoint {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,4 +427,169 @@ mod tests {
         assert_eq!(-10000, fp.0);
         assert_eq!(-1.0, fp.to_f64());
     }
+
+    #[tokio::test]
+    async fn fixedpoint_display() {
+        assert_eq!("0.1234", FixedPoint::from_f64(0.1234).to_string());
+        assert_eq!("-0.0500", FixedPoint::from_f64(-0.05).to_string());
+        assert_eq!("0.0000", FixedPoint::from_f64(-0.0).to_string());
+        assert_eq!(
+            "17014118346046923173168730371588410.5727",
+            FixedPoint(i128::MAX).to_string()
+        );
+        assert_eq!(
+            "-17014118346046923173168730371588410.5728",
+            FixedPoint(i128::MIN).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn fixedpoint_parse() {
+        assert_eq!(Ok(FixedPoint(15000)), "1.5".parse());
+        assert_eq!(Ok(FixedPoint(-2500)), "-.25".parse());
+        assert_eq!(Ok(FixedPoint(30000)), "+3".parse());
+        assert_eq!(Ok(FixedPoint(12345)), "1.234500".parse());
+        assert_eq!(
+            Ok(FixedPoint(i128::MIN)),
+            FixedPoint(i128::MIN).to_string().parse()
+        );
+
+        assert_eq!(Err(ParseFixedPointError::Empty), "-.".parse::<FixedPoint>());
+        assert_eq!(
+            Err(ParseFixedPointError::InvalidDigit),
+            "1,5".parse::<FixedPoint>()
+        );
+        assert_eq!(
+            Err(ParseFixedPointError::InvalidDigit),
+            " 1".parse::<FixedPoint>()
+        );
+        assert_eq!(
+            Err(ParseFixedPointError::TooManyDecimals),
+            "0.12345".parse::<FixedPoint>()
+        );
+        assert_eq!(
+            Err(ParseFixedPointError::Overflow),
+            "17014118346046923173168730371588410.5728".parse::<FixedPoint>()
+        );
+    }
+
+    #[tokio::test]
+    async fn fixedpoint_formatting_options() {
+        let fp = FixedPoint(-123_456_789_000);
+        assert_eq!("-12345678.9", fp.formatted().trim_zeros().to_string());
+        assert_eq!(
+            "-12,345,678.9000",
+            fp.formatted().thousands(',').to_string()
+        );
+        assert_eq!(
+            "-12 345 678.9",
+            fp.formatted().trim_zeros().thousands(' ').to_string()
+        );
+        assert_eq!("2", FixedPoint(20000).formatted().trim_zeros().to_string());
+        assert_eq!("0", FixedPoint(0).formatted().trim_zeros().to_string());
+        assert_eq!(
+            "999.0000",
+            FixedPoint(9990000).formatted().thousands(',').to_string()
+        );
+
+        let rounded = |s: &str, decimals, rounding| {
+            let fp: FixedPoint = s.parse().unwrap();
+            let rounded = fp.round_to(decimals, rounding).unwrap();
+            rounded.formatted().decimals(decimals).to_string()
+        };
+        assert_eq!("1.24", rounded("1.2350", 2, Rounding::HalfEven));
+        assert_eq!("1.23", rounded("1.2350", 2, Rounding::TowardZero));
+        assert_eq!("-2", rounded("-1.5", 0, Rounding::HalfUp));
+        assert_eq!("-1.5000", rounded("-1.5", 4, Rounding::Floor));
+    }
+
+    #[tokio::test]
+    async fn fixedpoint_mul_div() {
+        let fp = |s: &str| s.parse::<FixedPoint>().unwrap();
+
+        assert_eq!(fp("3.75"), fp("1.5") * fp("2.5"));
+        assert_eq!(fp("-0.6"), fp("1.5") / fp("-2.5"));
+        assert_eq!(fp("0.3333"), fp("1") / fp("3"));
+        assert_eq!(fp("0.6667"), fp("2") / fp("3"));
+        assert_eq!(None, fp("1").checked_div(fp("0"), Rounding::HalfEven));
+        assert_eq!(
+            None,
+            FixedPoint(i128::MAX).checked_mul(fp("2"), Rounding::HalfEven)
+        );
+
+        // 0.0001 * 0.5 is exactly half a unit
+        let half = |rounding| fp("0.0001").checked_mul(fp("0.5"), rounding).unwrap();
+        assert_eq!(fp("0"), half(Rounding::HalfEven));
+        assert_eq!(fp("0.0001"), half(Rounding::HalfUp));
+        assert_eq!(fp("0"), half(Rounding::TowardZero));
+        assert_eq!(fp("0.0001"), half(Rounding::AwayFromZero));
+
+        let neg = |rounding| fp("-0.0001").checked_div(fp("3"), rounding).unwrap();
+        assert_eq!(fp("-0.0001"), neg(Rounding::Floor));
+        assert_eq!(fp("0"), neg(Rounding::Ceiling));
+        assert_eq!(fp("0"), neg(Rounding::HalfUp));
+
+        assert_eq!(
+            Some(fp("2.5")),
+            fp("100").percentage(fp("2.5"), Rounding::HalfEven)
+        );
+        assert_eq!(
+            Some(fp("33.3333")),
+            fp("100").mul_ratio(1, 3, Rounding::HalfEven)
+        );
+        assert_eq!(fp("1.5"), -fp("-1.5"));
+        assert_eq!(fp("1.5"), fp("-1.5").abs());
+        assert_eq!(
+            fp("6"),
+            [fp("1"), fp("2"), fp("3")].iter().sum::<FixedPoint>()
+        );
+    }
+
+    #[tokio::test]
+    async fn fixedpoint_allocate() {
+        let fp = |s: &str| s.parse::<FixedPoint>().unwrap();
+
+        assert_eq!(
+            Some(vec![fp("33.3334"), fp("33.3333"), fp("33.3333")]),
+            fp("100").split(3)
+        );
+        assert_eq!(
+            Some(vec![fp("-0.0001"), fp("0"), fp("0")]),
+            fp("-0.0001").split(3)
+        );
+        assert_eq!(
+            Some(vec![fp("0.0004"), fp("0"), fp("0.0006")]),
+            fp("0.001").allocate(&[2, 0, 3])
+        );
+        assert_eq!(None, fp("1").allocate(&[0, 0]));
+        assert_eq!(None, fp("1").split(0));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn fixedpoint_allocation_sums(
+            raw in -1_000_000_000_000i128..1_000_000_000_000,
+            weights in proptest::collection::vec(0u64..1000, 1..20),
+        ) {
+            let fp = FixedPoint(raw);
+            if let Some(parts) = fp.allocate(&weights) {
+                proptest::prop_assert_eq!(weights.len(), parts.len());
+                proptest::prop_assert_eq!(fp, parts.iter().sum::<FixedPoint>());
+            } else {
+                proptest::prop_assert!(weights.iter().all(|w| *w == 0));
+            }
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn fixedpoint_round_trip(raw in proptest::num::i128::ANY) {
+            let fp = FixedPoint(raw);
+            proptest::prop_assert_eq!(Ok(fp), fp.to_string().parse());
+            proptest::prop_assert_eq!(Ok(fp), fp.formatted().trim_zeros().to_string().parse());
+
+            let grouped = fp.formatted().thousands(',').to_string();
+            proptest::prop_assert_eq!(Ok(fp), grouped.replace(',', "").parse());
+        }
+    }
 }
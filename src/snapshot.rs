@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+};
+
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+
+use crate::{
+    accounts::{Account, AccountStorage, AccountStore},
+    id::ClientId,
+};
+
+/// The version of the snapshot format written by this build, bump it whenever the layout of the
+/// account state changes in a way older snapshots can not be read with
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot file could not be read or written
+    Io(io::Error),
+    /// The snapshot file did not contain a valid snapshot
+    Format(serde_json::Error),
+    /// The snapshot was written with a format version this build does not understand
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot io error: {}", e),
+            SnapshotError::Format(e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported snapshot version {}, expected {}",
+                    v, SNAPSHOT_VERSION
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Format(e)
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    position: u64,
+    accounts: AccountsRef<'a>,
+}
+
+/// Every account of the store as a map by client, written as the store hands them out
+struct AccountsRef<'a>(&'a dyn AccountStore);
+
+impl Serialize for AccountsRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        let mut written = Ok(());
+        self.0
+            .for_each(&mut |client, account| {
+                if written.is_ok() {
+                    written = map.serialize_entry(&client, account);
+                }
+            })
+            .map_err(serde::ser::Error::custom)?;
+        written?;
+        map.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    /// how many input records the snapshot covers, snapshots from before this was added cover none
+    #[serde(default)]
+    position: u64,
+    accounts: BTreeMap<ClientId, Account>,
+}
+
+/// Writes the full state of every account to `pathname`, together with the number of input
+/// records, `position`, that has been applied to reach it.
+/// The snapshot is first written next to the target and then moved in place, so a crash
+/// halfway through never leaves a broken snapshot behind
+pub fn save(accounts: &AccountStorage, position: u64, pathname: &str) -> Result<(), SnapshotError> {
+    let tmp_path = format!("{}.tmp", pathname);
+    let mut file = io::BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(
+        &mut file,
+        &SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            position,
+            accounts: AccountsRef(accounts.accounts()),
+        },
+    )?;
+    file.flush()?;
+    file.get_ref().sync_all()?;
+    std::fs::rename(&tmp_path, pathname)?;
+    Ok(())
+}
+
+/// Loads the account state from a snapshot previously written with [`save`],
+/// together with the input position it was taken at
+pub fn load<'a>(
+    pathname: &str,
+    tx_path: &'a str,
+) -> Result<(AccountStorage<'a>, u64), SnapshotError> {
+    let file = io::BufReader::new(File::open(pathname)?);
+    let snapshot: Snapshot = serde_json::from_reader(file)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+    }
+    Ok((
+        AccountStorage::with_accounts(tx_path, snapshot.accounts),
+        snapshot.position,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::{Input, TransactionType},
+        policy::{DefaultPolicy, WithDisputeWindow},
+        FixedPoint, Timestamp, TransactionError,
+    };
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let path = std::env::temp_dir().join("toypa_snapshot_round_trip.json");
+        let path = path.to_str().unwrap();
+
+        let mut accounts = AccountStorage::new("");
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(5.1234)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+            Input::new(TransactionType::Deposit, 2, 3, Some(10.0)),
+            Input::new(TransactionType::Dispute, 2, 3, None),
+            Input::new(TransactionType::Chargeback, 2, 3, None),
+        ];
+        for input in inputs {
+            let _res = accounts.apply(input);
+        }
+        save(&accounts, 6, path).unwrap();
+
+        let (mut resumed, position) = load(path, "").unwrap();
+        assert_eq!(6, position);
+        let account = resumed.account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(50.0, account.available());
+        assert_eq!(5.1234, account.held());
+        assert!(resumed.account(ClientId(2)).unwrap().unwrap().locked());
+
+        // The history and dispute state survives, so the dispute can be resolved after resuming
+        let resolve = Input::new(TransactionType::Resolve, 1, 2, None);
+        assert!(resumed.apply(resolve).is_ok());
+        assert_eq!(
+            55.1234,
+            resumed.account(ClientId(1)).unwrap().unwrap().available()
+        );
+
+        // and a tx from before the snapshot can still be disputed
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None);
+        assert!(resumed.apply(dispute).is_ok());
+        assert_eq!(50.0, resumed.account(ClientId(1)).unwrap().unwrap().held());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_resumed_with_new_limit() {
+        let path = std::env::temp_dir().join("toypa_snapshot_limit.json");
+        let path = path.to_str().unwrap();
+        let fp = |s: &str| s.parse::<FixedPoint>().unwrap();
+
+        let mut accounts = AccountStorage::new("");
+        accounts
+            .set_credit_limits(BTreeMap::from([(ClientId(1), fp("10"))]))
+            .unwrap();
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 1, 1, Some(5.0)));
+        save(&accounts, 1, path).unwrap();
+
+        // the limit of the next day is lower, it applies to the resumed account as well
+        let (mut resumed, _) = load(path, "").unwrap();
+        resumed
+            .set_credit_limits(BTreeMap::from([(ClientId(1), fp("2"))]))
+            .unwrap();
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 2, Some(8.0));
+        assert!(resumed.apply(withdrawal).is_err());
+        let withdrawal = Input::new(TransactionType::Withdrawal, 1, 3, Some(7.0));
+        assert!(resumed.apply(withdrawal).is_ok());
+        assert_eq!(
+            fp("2"),
+            resumed.account(ClientId(1)).unwrap().unwrap().credit_used()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_resumed_with_dispute_window() {
+        let path = std::env::temp_dir().join("toypa_snapshot_window.json");
+        let path = path.to_str().unwrap();
+        let day = crate::timestamp::MILLIS_PER_DAY;
+
+        let mut accounts = AccountStorage::new("");
+        let deposit = Input::new(TransactionType::Deposit, 1, 1, Some(5.0))
+            .with_timestamp(Timestamp::from_millis(0));
+        let _res = accounts.apply(deposit);
+        save(&accounts, 1, path).unwrap();
+
+        // the window is set after resuming, it holds for the account from before as well
+        let (mut resumed, _) = load(path, "").unwrap();
+        resumed.set_policy(Box::new(WithDisputeWindow {
+            policy: Box::new(DefaultPolicy),
+            dispute_window: 30 * day,
+        }));
+        let dispute = Input::new(TransactionType::Dispute, 1, 1, None)
+            .with_timestamp(Timestamp::from_millis(31 * day));
+        assert!(matches!(
+            resumed.apply(dispute),
+            Err(TransactionError::DisputeWindowExpired)
+        ));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_version_is_checked() {
+        let path = std::env::temp_dir().join("toypa_snapshot_version.json");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, r#"{"version": 999, "accounts": {}}"#).unwrap();
+
+        assert!(matches!(
+            load(path, ""),
+            Err(SnapshotError::UnsupportedVersion(999))
+        ));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
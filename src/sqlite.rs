@@ -0,0 +1,555 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, ToSql,
+};
+
+use crate::{
+    accounts::{Account, AccountStore, DisputeState, StoreError},
+    id::{ClientId, TxId, TxIdParseError},
+    input::{Input, TransactionType},
+    timestamp::Timestamp,
+};
+
+/// Amounts are exact decimal text, the way the engine writes them everywhere else, and timestamps
+/// epoch milliseconds. Transaction ids are text as they can be UUIDs
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    available TEXT NOT NULL,
+    held TEXT NOT NULL,
+    locked INTEGER NOT NULL,
+    credit_limit TEXT NOT NULL,
+    last_timestamp INTEGER
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    client INTEGER NOT NULL,
+    tx TEXT NOT NULL,
+    type TEXT NOT NULL,
+    amount TEXT,
+    timestamp INTEGER,
+    PRIMARY KEY (client, tx)
+);
+CREATE TABLE IF NOT EXISTS disputes (
+    client INTEGER NOT NULL,
+    tx TEXT NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (client, tx)
+);
+";
+
+#[derive(Debug)]
+pub enum SqliteError {
+    /// The database could not be opened, read or written
+    Sqlite(rusqlite::Error),
+    /// A stored value could not be read back
+    InvalidValue {
+        table: &'static str,
+        client: ClientId,
+        value: String,
+    },
+}
+
+impl Display for SqliteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqliteError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            SqliteError::InvalidValue {
+                table,
+                client,
+                value,
+            } => write!(
+                f,
+                "invalid value {:?} in table {} for client {}",
+                value, table, client
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SqliteError {}
+
+impl From<rusqlite::Error> for SqliteError {
+    fn from(e: rusqlite::Error) -> Self {
+        SqliteError::Sqlite(e)
+    }
+}
+
+/// SQLite integers are signed, so client ids are stored with the same bits as an `i64`, and the
+/// ids above `i64::MAX` come out negative in the database
+impl ToSql for ClientId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0 as i64))
+    }
+}
+
+impl FromSql for ClientId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(ClientId(value.as_i64()? as u64))
+    }
+}
+
+impl ToSql for TxId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for TxId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: TxIdParseError| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+fn dispute_state_name(state: DisputeState) -> &'static str {
+    match state {
+        DisputeState::Started => "opened",
+        DisputeState::Resolved => "resolved",
+        DisputeState::Reimbursed => "charged_back",
+    }
+}
+
+fn parse<T: FromStr>(
+    table: &'static str,
+    client: ClientId,
+    value: String,
+) -> Result<T, SqliteError> {
+    value.parse().map_err(|_| SqliteError::InvalidValue {
+        table,
+        client,
+        value,
+    })
+}
+
+/// Accounts kept in an SQLite database, with a table for the accounts, the transactions that can
+/// still be disputed and the disputes.
+///
+/// Every transaction is applied in an SQLite transaction of its own, reading only the rows it
+/// needs, so the database always holds the state after some whole number of transactions
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Opens the database, creating it and its tables if they do not exist yet
+    pub fn open(path: &str) -> Result<Self, SqliteError> {
+        let connection = Connection::open(path)?;
+        // committing every row would otherwise wait for the disk every time
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+
+    /// Replaces everything in the database with the accounts of the store, for a run resuming
+    /// from a snapshot or checkpoint, so no account or transaction newer than that is left behind
+    pub fn replace_all(&mut self, accounts: &dyn AccountStore) -> Result<(), StoreError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute_batch(
+            "DELETE FROM accounts; DELETE FROM transactions; DELETE FROM disputes;",
+        )?;
+        let mut res = Ok(());
+        accounts.for_each(&mut |client, account| {
+            if res.is_ok() {
+                res = write_all(&transaction, client, account);
+            }
+        })?;
+        res?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Reads the accounts of every client, or only of `client`. With `tx` given as well, only that
+/// transaction of the client is read, all a change for that transaction needs
+fn read_accounts(
+    connection: &Connection,
+    client: Option<ClientId>,
+    tx: Option<TxId>,
+) -> Result<BTreeMap<ClientId, Account>, SqliteError> {
+    let mut by_client: Vec<&dyn ToSql> = Vec::new();
+    let mut filter = "";
+    if let Some(client) = &client {
+        by_client.push(client);
+        filter = " WHERE client = ?1";
+    }
+    let mut by_tx = by_client.clone();
+    let mut tx_filter = filter;
+    if let (Some(_), Some(tx)) = (&client, &tx) {
+        by_tx.push(tx);
+        tx_filter = " WHERE client = ?1 AND tx = ?2";
+    }
+
+    let mut accounts = BTreeMap::new();
+    let mut statement = connection.prepare(&format!(
+        "SELECT client, available, held, locked, credit_limit, last_timestamp
+         FROM accounts{}",
+        filter
+    ))?;
+    let mut rows = statement.query(by_client.as_slice())?;
+    while let Some(row) = rows.next()? {
+        let client: ClientId = row.get(0)?;
+        let account = Account {
+            available: parse("accounts", client, row.get(1)?)?,
+            held: parse("accounts", client, row.get(2)?)?,
+            locked: row.get(3)?,
+            credit_limit: parse("accounts", client, row.get(4)?)?,
+            last_timestamp: row.get::<_, Option<i64>>(5)?.map(Timestamp::from_millis),
+            ..Account::new()
+        };
+        accounts.insert(client, account);
+    }
+
+    let mut statement = connection.prepare(&format!(
+        "SELECT client, tx, type, amount, timestamp FROM transactions{}",
+        tx_filter
+    ))?;
+    let mut rows = statement.query(by_tx.as_slice())?;
+    while let Some(row) = rows.next()? {
+        let client: ClientId = row.get(0)?;
+        let tx: TxId = row.get(1)?;
+        let r#type: TransactionType = parse("transactions", client, row.get(2)?)?;
+        let amount = match row.get_ref(3)? {
+            ValueRef::Null => None,
+            // amounts are exact decimal text, a float would already have lost digits
+            ValueRef::Real(amount) => {
+                return Err(SqliteError::InvalidValue {
+                    table: "transactions",
+                    client,
+                    value: amount.to_string(),
+                })
+            }
+            _ => Some(parse("transactions", client, row.get(3)?)?),
+        };
+        let mut input = Input::with_ids(r#type, client, tx, amount);
+        if let Some(millis) = row.get::<_, Option<i64>>(4)? {
+            input = input.with_timestamp(Timestamp::from_millis(millis));
+        }
+        if let Some(account) = accounts.get_mut(&client) {
+            account.tx_history.insert(tx, input);
+        }
+    }
+
+    // every dispute, as a withdrawal has to know whether any of them is still open
+    let mut statement =
+        connection.prepare(&format!("SELECT client, tx, state FROM disputes{}", filter))?;
+    let mut rows = statement.query(by_client.as_slice())?;
+    while let Some(row) = rows.next()? {
+        let client: ClientId = row.get(0)?;
+        let tx: TxId = row.get(1)?;
+        let state = match row.get::<_, String>(2)?.as_str() {
+            "opened" => DisputeState::Started,
+            "resolved" => DisputeState::Resolved,
+            "charged_back" => DisputeState::Reimbursed,
+            other => {
+                return Err(SqliteError::InvalidValue {
+                    table: "disputes",
+                    client,
+                    value: other.to_string(),
+                })
+            }
+        };
+        if let Some(account) = accounts.get_mut(&client) {
+            account.disputes.insert(tx, state);
+        }
+    }
+    Ok(accounts)
+}
+
+/// Writes the account with every transaction and dispute it keeps
+fn write_all(
+    connection: &Connection,
+    client: ClientId,
+    account: &Account,
+) -> Result<(), SqliteError> {
+    write_account(connection, client, account)?;
+    for (tx, input) in &account.tx_history {
+        write_transaction(connection, client, *tx, input)?;
+    }
+    for (tx, state) in &account.disputes {
+        write_dispute(connection, client, *tx, *state)?;
+    }
+    Ok(())
+}
+
+fn write_account(
+    connection: &Connection,
+    client: ClientId,
+    account: &Account,
+) -> Result<(), SqliteError> {
+    connection.execute(
+        "INSERT INTO accounts
+         (client, available, held, locked, credit_limit, last_timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (client) DO UPDATE SET
+         available = excluded.available, held = excluded.held, locked = excluded.locked,
+         credit_limit = excluded.credit_limit, last_timestamp = excluded.last_timestamp",
+        params![
+            client,
+            account.available.to_string(),
+            account.held.to_string(),
+            account.locked,
+            account.credit_limit.to_string(),
+            account.last_timestamp.map(Timestamp::millis),
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_transaction(
+    connection: &Connection,
+    client: ClientId,
+    tx: TxId,
+    input: &Input,
+) -> Result<(), SqliteError> {
+    connection.execute(
+        "INSERT OR REPLACE INTO transactions (client, tx, type, amount, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            client,
+            tx,
+            input.r#type().to_string(),
+            input.amount_as_fp().map(|amount| amount.to_string()),
+            input.timestamp().map(Timestamp::millis),
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_dispute(
+    connection: &Connection,
+    client: ClientId,
+    tx: TxId,
+    state: DisputeState,
+) -> Result<(), SqliteError> {
+    connection.execute(
+        "INSERT OR REPLACE INTO disputes (client, tx, state) VALUES (?1, ?2, ?3)",
+        params![client, tx, dispute_state_name(state)],
+    )?;
+    Ok(())
+}
+
+impl AccountStore for SqliteStore {
+    fn load(&self, client: ClientId) -> Result<Option<Account>, StoreError> {
+        Ok(read_accounts(&self.connection, Some(client), None)?.remove(&client))
+    }
+
+    fn update(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        create: &mut dyn FnMut() -> Account,
+        change: &mut dyn FnMut(&mut Account),
+    ) -> Result<(), StoreError> {
+        let transaction = self.connection.transaction()?;
+        let mut account = read_accounts(&transaction, Some(client), Some(tx))?
+            .remove(&client)
+            .unwrap_or_else(create);
+        change(&mut account);
+        write_account(&transaction, client, &account)?;
+        // only the transactions the account keeps, those that can be disputed
+        if let Some(input) = account.tx_history.get(&tx) {
+            write_transaction(&transaction, client, tx, input)?;
+        }
+        if let Some(state) = account.disputes.get(&tx) {
+            write_dispute(&transaction, client, tx, *state)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn upsert(&mut self, client: ClientId, account: Account) -> Result<(), StoreError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM transactions WHERE client = ?1", [client])?;
+        transaction.execute("DELETE FROM disputes WHERE client = ?1", [client])?;
+        write_all(&transaction, client, &account)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(ClientId, &Account)) -> Result<(), StoreError> {
+        for (client, account) in read_accounts(&self.connection, None, None)? {
+            visit(client, &account);
+        }
+        Ok(())
+    }
+
+    fn transaction(&self, client: ClientId, tx: TxId) -> Result<Option<Input>, StoreError> {
+        let mut accounts = read_accounts(&self.connection, Some(client), Some(tx))?;
+        Ok(accounts
+            .get_mut(&client)
+            .and_then(|account| account.tx_history.remove(&tx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{accounts::AccountStorage, FixedPoint};
+
+    fn clients(store: &dyn AccountStore) -> Vec<u64> {
+        let mut clients = Vec::new();
+        store
+            .for_each(&mut |client, _| clients.push(client.0))
+            .unwrap();
+        clients
+    }
+
+    #[tokio::test]
+    async fn sqlite_storage_survives_restart() {
+        let path = std::env::temp_dir().join("toypa_sqlite_storage.db");
+        let _res = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let mut accounts =
+            AccountStorage::with_store("", Box::new(SqliteStore::open(path).unwrap()));
+        let inputs = vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(10.5))
+                .with_timestamp(Timestamp::from_millis(1000)),
+            Input::new(TransactionType::Deposit, 1, 2, Some(4.25)),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(1.0)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+            Input::new(TransactionType::Deposit, 2, 4, Some(3.0)),
+            Input::new(TransactionType::Dispute, 2, 4, None),
+            Input::new(TransactionType::Chargeback, 2, 4, None),
+        ];
+        for input in inputs {
+            accounts.apply(input).unwrap();
+        }
+        // a rejected transaction still creates the account
+        assert!(accounts
+            .apply(Input::new(TransactionType::Withdrawal, 3, 5, Some(1.0)))
+            .is_err());
+        drop(accounts);
+
+        let mut store = SqliteStore::open(path).unwrap();
+        let state: String = store
+            .connection
+            .query_row(
+                "SELECT state FROM disputes WHERE client = 2 AND tx = 4",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("charged_back", state);
+        let available: String = store
+            .connection
+            .query_row(
+                "SELECT available FROM accounts WHERE client = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("3.2500", available);
+        let amount: String = store
+            .connection
+            .query_row(
+                "SELECT amount FROM transactions WHERE client = 1 AND tx = '2'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!("4.2500", amount);
+
+        assert_eq!(vec![1, 2, 3], clients(&store));
+        let account = store.load(ClientId(1)).unwrap().unwrap();
+        assert_eq!(FixedPoint::from_f64(10.5), account.held());
+        assert_eq!(Some(Timestamp::from_millis(1000)), account.last_timestamp);
+        assert_eq!(
+            Some(DisputeState::Started),
+            account.dispute_state(TxId::Number(1))
+        );
+        assert!(store.load(ClientId(2)).unwrap().unwrap().locked());
+        assert_eq!(
+            Some(FixedPoint::from_f64(4.25)),
+            store
+                .transaction(ClientId(1), TxId::Number(2))
+                .unwrap()
+                .and_then(|input| input.amount_as_fp())
+        );
+
+        // resuming from an older state leaves nothing of the later one behind
+        let mut older = AccountStorage::new("");
+        older
+            .apply(Input::new(TransactionType::Deposit, 1, 1, Some(10.5)))
+            .unwrap();
+        store.replace_all(older.accounts()).unwrap();
+        assert_eq!(vec![1], clients(&store));
+        let replaced = store.load(ClientId(1)).unwrap().unwrap();
+        assert_eq!(FixedPoint::from_f64(10.5), replaced.available());
+        assert_eq!(None, replaced.dispute_state(TxId::Number(1)));
+
+        // an account stored whole replaces everything the client had
+        store.upsert(ClientId(1), account).unwrap();
+        assert_eq!(
+            Some(DisputeState::Started),
+            store
+                .load(ClientId(1))
+                .unwrap()
+                .unwrap()
+                .dispute_state(TxId::Number(1))
+        );
+        drop(store);
+
+        // the dispute opened before the restart can still be resolved after it
+        let mut accounts =
+            AccountStorage::with_store("", Box::new(SqliteStore::open(path).unwrap()));
+        accounts
+            .apply(Input::new(TransactionType::Resolve, 1, 1, None))
+            .unwrap();
+        assert_eq!(
+            FixedPoint::from_f64(13.75),
+            accounts.account(ClientId(1)).unwrap().unwrap().available()
+        );
+    }
+
+    #[tokio::test]
+    async fn sqlite_stores_every_client_id() {
+        let path = std::env::temp_dir().join("toypa_sqlite_client_ids.db");
+        let _res = std::fs::remove_file(&path);
+        let mut store = SqliteStore::open(path.to_str().unwrap()).unwrap();
+
+        for client in [0, i64::MAX as u64 + 1, u64::MAX] {
+            store.upsert(ClientId(client), Account::new()).unwrap();
+        }
+        assert_eq!(vec![0, i64::MAX as u64 + 1, u64::MAX], clients(&store));
+        assert!(store.load(ClientId(u64::MAX)).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn sqlite_rejects_float_amounts() {
+        let path = std::env::temp_dir().join("toypa_sqlite_float_amounts.db");
+        let _res = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        // a table from before amounts were kept as text
+        let connection = Connection::open(path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE transactions (client INTEGER NOT NULL, tx TEXT NOT NULL,
+                    type TEXT NOT NULL, amount REAL, timestamp INTEGER, PRIMARY KEY (client, tx));",
+            )
+            .unwrap();
+        drop(connection);
+
+        let mut store = SqliteStore::open(path).unwrap();
+        store.upsert(ClientId(1), Account::new()).unwrap();
+        store
+            .connection
+            .execute(
+                "INSERT INTO transactions VALUES (1, '1', 'deposit', 10.5, NULL)",
+                [],
+            )
+            .unwrap();
+        assert_eq!(
+            "invalid value \"10.5\" in table transactions for client 1",
+            store.load(ClientId(1)).err().unwrap().to_string()
+        );
+    }
+}
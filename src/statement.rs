@@ -0,0 +1,249 @@
+use std::{io, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{
+    accounts::DisputeState,
+    history::{Balance, History},
+    id::{ClientId, TxId},
+    output::{Amount, Precision},
+};
+
+/// The formats a statement can be written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatementFormat {
+    /// A csv file with a header
+    Csv,
+    /// A single pretty printed json array
+    Json,
+    /// Aligned columns for people to read, a block per client
+    Text,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatementFormat::Csv),
+            "json" => Ok(StatementFormat::Json),
+            "text" => Ok(StatementFormat::Text),
+            other => Err(format!("unknown statement format: {}", other)),
+        }
+    }
+}
+
+/// A line of a statement, a transaction with the balances right after it
+#[derive(Serialize)]
+struct Line {
+    client: ClientId,
+    /// None for the opening balance
+    tx: Option<TxId>,
+    r#type: String,
+    timestamp: Option<String>,
+    /// the funds moved by the transaction
+    amount: Option<Amount>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    /// what happened to the dispute on the tx, if anything
+    dispute: Option<&'static str>,
+    /// whether the transaction locked the account
+    locked: bool,
+}
+
+impl Line {
+    fn new(client: ClientId, r#type: String, balance: Balance, precision: Precision) -> Self {
+        let available = Amount::new(balance.available, precision);
+        let held = Amount::new(balance.held, precision);
+        Self {
+            client,
+            tx: None,
+            r#type,
+            timestamp: None,
+            amount: None,
+            available,
+            held,
+            total: available + held,
+            dispute: None,
+            locked: false,
+        }
+    }
+}
+
+/// The opening balance of the client, followed by every transaction applied to it
+fn lines(history: &History, client: ClientId, precision: Precision) -> Vec<Line> {
+    let mut balance = history.opening(client);
+    let mut lines = vec![Line::new(client, "opening".to_string(), balance, precision)];
+    for applied in history.applied(client) {
+        balance = applied.after(balance);
+        lines.push(Line {
+            tx: Some(applied.tx),
+            timestamp: applied.timestamp.map(|timestamp| timestamp.to_string()),
+            amount: applied
+                .movement
+                .map(|movement| Amount::new(movement.amount, precision)),
+            dispute: applied.dispute.map(|state| match state {
+                DisputeState::Started => "opened",
+                DisputeState::Resolved => "resolved",
+                DisputeState::Reimbursed => "charged_back",
+            }),
+            locked: applied.locked,
+            ..Line::new(client, applied.r#type.to_string(), balance, precision)
+        });
+    }
+    lines
+}
+
+/// Writes the statement of a single client, or of every client in the history, in the given format
+pub fn write_statement<W: io::Write>(
+    history: &History,
+    client: Option<ClientId>,
+    format: StatementFormat,
+    precision: Precision,
+    mut writer: W,
+) -> io::Result<()> {
+    let clients: Vec<ClientId> = match client {
+        Some(client) => vec![client],
+        None => history.clients().collect(),
+    };
+    let statements = clients
+        .into_iter()
+        .map(|client| lines(history, client, precision));
+
+    match format {
+        StatementFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            for line in statements.flatten() {
+                csv.serialize(line)?;
+            }
+            csv.flush()
+        }
+        StatementFormat::Json => {
+            let lines: Vec<Line> = statements.flatten().collect();
+            serde_json::to_writer_pretty(&mut writer, &lines)?;
+            writer.write_all(b"\n")?;
+            writer.flush()
+        }
+        StatementFormat::Text => {
+            for (i, lines) in statements.enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                text(&mut writer, &lines)?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+/// The lines of a single client as aligned columns
+fn text<W: io::Write>(writer: &mut W, lines: &[Line]) -> io::Result<()> {
+    let Some(first) = lines.first() else {
+        return Ok(());
+    };
+    writeln!(writer, "Client {}", first.client)?;
+    writeln!(
+        writer,
+        "{:<24}  {:<10}  {:>10}  {:>12}  {:>12}  {:>12}  {:>12}  notes",
+        "timestamp", "type", "tx", "amount", "available", "held", "total"
+    )?;
+    let column = |value: Option<String>| value.unwrap_or_default();
+    for line in lines {
+        let mut notes = Vec::new();
+        if let Some(dispute) = line.dispute {
+            notes.push(format!("dispute {}", dispute.replace('_', " ")));
+        }
+        if line.locked {
+            notes.push("account locked".to_string());
+        }
+        let row = format!(
+            "{:<24}  {:<10}  {:>10}  {:>12}  {:>12}  {:>12}  {:>12}  {}",
+            column(line.timestamp.clone()),
+            line.r#type,
+            column(line.tx.map(|tx| tx.to_string())),
+            column(line.amount.map(|amount| amount.to_string())),
+            line.available,
+            line.held,
+            line.total,
+            notes.join(", ")
+        );
+        writeln!(writer, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accounts::AccountStorage,
+        input::{Input, TransactionType},
+        Engine,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn history() -> Arc<Mutex<History>> {
+        let mut accounts = AccountStorage::new("");
+        let _res = accounts.apply(Input::new(TransactionType::Deposit, 2, 1, Some(3.0)));
+        let history = Arc::new(Mutex::new(History::new(&accounts).unwrap()));
+        let mut engine = Engine::with_storage(accounts);
+        engine.add_observer(history.clone());
+        engine.apply_iter(vec![
+            Input::new(TransactionType::Deposit, 1, 2, Some(10.0))
+                .with_timestamp("2023-01-05T09:30:00Z".parse().unwrap()),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(2.5)),
+            // rejected, it does not show up
+            Input::new(TransactionType::Withdrawal, 1, 4, Some(50.0)),
+            Input::new(TransactionType::Deposit, 1, 5, Some(5.0)),
+            Input::new(TransactionType::Dispute, 1, 2, None),
+            Input::new(TransactionType::Resolve, 1, 2, None),
+            Input::new(TransactionType::Dispute, 1, 5, None),
+            Input::new(TransactionType::Chargeback, 1, 5, None),
+        ]);
+        history
+    }
+
+    fn written(client: Option<ClientId>, format: StatementFormat) -> String {
+        let mut out = Vec::new();
+        let history = history();
+        let history = history.lock().unwrap();
+        write_statement(&history, client, format, Precision::default(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn statement_csv() {
+        assert_eq!(
+            "client,tx,type,timestamp,amount,available,held,total,dispute,locked\n\
+             1,,opening,,,0.0000,0.0000,0.0000,,false\n\
+             1,2,deposit,2023-01-05T09:30:00.000Z,10.0000,10.0000,0.0000,10.0000,,false\n\
+             1,3,withdrawal,,2.5000,7.5000,0.0000,7.5000,,false\n\
+             1,5,deposit,,5.0000,12.5000,0.0000,12.5000,,false\n\
+             1,2,dispute,,10.0000,2.5000,10.0000,12.5000,opened,false\n\
+             1,2,resolve,,10.0000,12.5000,0.0000,12.5000,resolved,false\n\
+             1,5,dispute,,5.0000,7.5000,5.0000,12.5000,opened,false\n\
+             1,5,chargeback,,5.0000,7.5000,0.0000,7.5000,charged_back,true\n",
+            written(Some(ClientId(1)), StatementFormat::Csv)
+        );
+    }
+
+    #[tokio::test]
+    async fn statement_every_client() {
+        let parsed: serde_json::Value =
+            serde_json::from_str(&written(None, StatementFormat::Json)).unwrap();
+        let lines = parsed.as_array().unwrap();
+        assert_eq!(9, lines.len());
+        // client 2 only has the balance it started with
+        assert_eq!(2, lines[8]["client"]);
+        assert_eq!("opening", lines[8]["type"]);
+        assert_eq!("3.0000", lines[8]["available"]);
+
+        let text = written(None, StatementFormat::Text);
+        let blocks: Vec<&str> = text.split("\n\n").collect();
+        assert_eq!(2, blocks.len());
+        assert!(blocks[0].starts_with("Client 1\ntimestamp"));
+        assert!(blocks[0].contains("dispute charged back, account locked"));
+        assert!(blocks[1].starts_with("Client 2\n"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Milliseconds in one day, used when converting day based windows
+pub const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// A point in time, stored as milliseconds since the unix epoch.
+/// It can be parsed either from an RFC 3339 string or from the epoch millis directly
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_millis(millis: i64) -> Self {
+        Self(millis)
+    }
+
+    /// Get the timestamp as milliseconds since the unix epoch
+    pub fn millis(self) -> i64 {
+        self.0
+    }
+
+    /// The number of milliseconds that has passed since `earlier`, negative if `earlier` is later.
+    /// Saturates at the bounds of an `i64` for timestamps too far apart
+    pub fn millis_since(self, earlier: Timestamp) -> i64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct TimestampParseError(String);
+
+impl Display for TimestampParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' is neither an RFC 3339 timestamp nor epoch milliseconds",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for TimestampParseError {}
+
+impl FromStr for Timestamp {
+    type Err = TimestampParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(millis) = s.parse::<i64>() {
+            return Ok(Self(millis));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|dt| Self(dt.timestamp_millis()))
+            .map_err(|_| TimestampParseError(s.to_string()))
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match DateTime::<Utc>::from_timestamp_millis(self.0) {
+            Some(dt) => f.write_str(&dt.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Serialized as epoch milliseconds, so it round trips without any loss
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TimestampVisitor;
+
+        impl<'de> de::Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an RFC 3339 timestamp or epoch milliseconds")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Timestamp(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                i64::try_from(v)
+                    .map(Timestamp)
+                    .map_err(|_| E::custom("epoch milliseconds out of range"))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn timestamp_parsing() {
+        let epoch: Timestamp = "1650000000000".parse().unwrap();
+        assert_eq!(1650000000000, epoch.millis());
+
+        let rfc: Timestamp = "2022-04-15T05:20:00Z".parse().unwrap();
+        assert_eq!(epoch, rfc);
+
+        let offset: Timestamp = "2022-04-15T07:20:00+02:00".parse().unwrap();
+        assert_eq!(epoch, offset);
+
+        assert!("yesterday".parse::<Timestamp>().is_err());
+        assert_eq!("2022-04-15T05:20:00.000Z", epoch.to_string());
+
+        let (min, max) = (
+            Timestamp::from_millis(i64::MIN),
+            Timestamp::from_millis(i64::MAX),
+        );
+        assert_eq!(i64::MAX, max.millis_since(min));
+        assert_eq!(i64::MIN, min.millis_since(max));
+    }
+}
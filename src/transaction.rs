@@ -1,43 +1,122 @@
-use crate::{input::Input, FixedPoint};
+use crate::{
+    accounts::TransactionError,
+    id::{ClientId, TxId},
+    input::{Input, TransactionType},
+    FixedPoint,
+};
 
 /// the different types of transactions that can occur
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
     Dispute(Dispute),
     Resolve(Resolve),
     Chargeback(Chargeback),
+    SetLimit(SetLimit),
 }
+
+impl Transaction {
+    /// Get the client the transaction is for
+    pub fn client(&self) -> ClientId {
+        match self {
+            Transaction::Deposit(t) => t.client,
+            Transaction::Withdrawal(t) => t.client,
+            Transaction::Dispute(t) => t.client,
+            Transaction::Resolve(t) => t.client,
+            Transaction::Chargeback(t) => t.client,
+            Transaction::SetLimit(t) => t.client,
+        }
+    }
+
+    /// Get the transaction id, for disputes, resolves and chargebacks this is the disputed tx
+    pub fn tx(&self) -> TxId {
+        match self {
+            Transaction::Deposit(t) => t.tx,
+            Transaction::Withdrawal(t) => t.tx,
+            Transaction::Dispute(t) => t.tx,
+            Transaction::Resolve(t) => t.tx,
+            Transaction::Chargeback(t) => t.tx,
+            Transaction::SetLimit(t) => t.tx,
+        }
+    }
+}
+
+/// Turns a raw input into a transaction, checking that the input is valid for its type
 impl TryFrom<Input> for Transaction {
-    fn try_from(input: Input) -> Self {}
+    type Error = TransactionError;
+
+    fn try_from(input: Input) -> Result<Self, Self::Error> {
+        if !input.valid() {
+            return Err(TransactionError::InvalidTx);
+        }
+        let client = input.client();
+        let tx = input.tx();
+        // Safe because of the validity check on the input
+        let amount = || input.amount_as_fp().unwrap();
+        let transaction = match input.r#type() {
+            TransactionType::Deposit => Transaction::Deposit(Deposit {
+                client,
+                tx,
+                amount: amount(),
+            }),
+            TransactionType::Withdrawal => Transaction::Withdrawal(Withdrawal {
+                client,
+                tx,
+                amount: amount(),
+            }),
+            TransactionType::Dispute => Transaction::Dispute(Dispute { client, tx }),
+            TransactionType::Resolve => Transaction::Resolve(Resolve { client, tx }),
+            TransactionType::Chargeback => Transaction::Chargeback(Chargeback { client, tx }),
+            TransactionType::SetLimit => Transaction::SetLimit(SetLimit {
+                client,
+                tx,
+                limit: amount(),
+            }),
+        };
+        Ok(transaction)
+    }
 }
 
 /// A chargeback transaction request
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chargeback {
-    client: u16,
-    tx: u32,
+    pub client: ClientId,
+    pub tx: TxId,
 }
 
 /// Marking a tx as resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Resolve {
-    client: u16,
-    tx: u32,
+    pub client: ClientId,
+    pub tx: TxId,
 }
 
 /// Marking a tx as disputed, for a certain client
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Dispute {
-    client: u16,
-    tx: u32,
+    pub client: ClientId,
+    pub tx: TxId,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Withdrawal {
-    client: u16,
-    tx: u32,
-    amount: FixedPoint,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: FixedPoint,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Deposit {
-    client: u16,
-    tx: u32,
-    amount: FixedPoint,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: FixedPoint,
+}
+
+/// Setting the credit limit of a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetLimit {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub limit: FixedPoint,
 }
@@ -0,0 +1,337 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accounts::AccountStorage,
+    input::Input,
+    snapshot::{self, SnapshotError},
+};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "wal.log";
+
+#[derive(Debug)]
+pub enum WalError {
+    /// The log could not be read or written
+    Io(io::Error),
+    /// A record could not be written to the log
+    Format(serde_json::Error),
+    /// The checkpoint could not be read or written
+    Snapshot(SnapshotError),
+}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalError::Io(e) => write!(f, "write-ahead log io error: {}", e),
+            WalError::Format(e) => write!(f, "invalid write-ahead log record: {}", e),
+            WalError::Snapshot(e) => write!(f, "write-ahead log checkpoint: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalError {}
+
+impl From<io::Error> for WalError {
+    fn from(e: io::Error) -> Self {
+        WalError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for WalError {
+    fn from(e: serde_json::Error) -> Self {
+        WalError::Format(e)
+    }
+}
+
+impl From<SnapshotError> for WalError {
+    fn from(e: SnapshotError) -> Self {
+        WalError::Snapshot(e)
+    }
+}
+
+/// A line in the log, the input together with its position in the input file
+#[derive(Serialize, Deserialize)]
+struct WalRecord {
+    position: u64,
+    input: Input,
+}
+
+/// What was found in the log directory when opening it
+pub struct Recovered<'a> {
+    /// The state at the latest checkpoint, if a checkpoint has been taken
+    pub accounts: Option<AccountStorage<'a>>,
+    /// Inputs logged after the checkpoint, these have to be applied again in order
+    pub tail: Vec<Input>,
+}
+
+/// Write-ahead log of the inputs being applied, with periodic checkpoints of the account state.
+///
+/// Every input is appended to the log before it is applied, and every `checkpoint_every` inputs
+/// the account state is written as a snapshot and the log is emptied. The log is flushed on
+/// every append, so it survives the process dying, but it is only synced to disk at checkpoints.
+pub struct WriteAheadLog {
+    snapshot_path: String,
+    log_path: String,
+    log: BufWriter<File>,
+    /// position of the latest logged input
+    position: u64,
+    /// position covered by the latest checkpoint
+    checkpoint_position: u64,
+    checkpoint_every: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in `dir`, creating it if needed, and recovers whatever state a previous
+    /// run left behind
+    pub fn open<'a>(
+        dir: &str,
+        tx_path: &'a str,
+        checkpoint_every: u64,
+    ) -> Result<(Self, Recovered<'a>), WalError> {
+        fs::create_dir_all(dir)?;
+        let snapshot_path = Path::new(dir).join(SNAPSHOT_FILE);
+        let log_path = Path::new(dir).join(LOG_FILE);
+        let snapshot_path = snapshot_path.to_string_lossy().into_owned();
+        let log_path = log_path.to_string_lossy().into_owned();
+
+        let (accounts, checkpoint_position) = if Path::new(&snapshot_path).exists() {
+            let (accounts, position) = snapshot::load(&snapshot_path, tx_path)?;
+            (Some(accounts), position)
+        } else {
+            (None, 0)
+        };
+
+        let mut records = Vec::new();
+        if Path::new(&log_path).exists() {
+            for line in BufReader::new(File::open(&log_path)?).lines() {
+                // A line that can not be parsed was torn by a crash during the write,
+                // that input was never applied, and nothing can come after it
+                match serde_json::from_str::<WalRecord>(&line?) {
+                    Ok(record) => records.push(record),
+                    Err(_) => break,
+                }
+            }
+        }
+        // The log may still hold inputs that made it into the checkpoint, if the process
+        // died between writing the snapshot and emptying the log
+        records.retain(|record| record.position > checkpoint_position);
+
+        // Rewrite the log without the torn tail, so new records are not appended after it
+        let tmp_path = format!("{}.tmp", log_path);
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        for record in &records {
+            serde_json::to_writer(&mut tmp, record)?;
+            tmp.write_all(b"\n")?;
+        }
+        tmp.flush()?;
+        tmp.get_ref().sync_all()?;
+        fs::rename(&tmp_path, &log_path)?;
+
+        let position = records
+            .last()
+            .map_or(checkpoint_position, |record| record.position);
+        let log = BufWriter::new(OpenOptions::new().append(true).open(&log_path)?);
+
+        let wal = Self {
+            snapshot_path,
+            log_path,
+            log,
+            position,
+            checkpoint_position,
+            checkpoint_every,
+        };
+        let recovered = Recovered {
+            accounts,
+            tail: records.into_iter().map(|record| record.input).collect(),
+        };
+        Ok((wal, recovered))
+    }
+
+    /// The position of the latest logged input, every input up to and including this has been
+    /// applied once the recovered tail has been replayed
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Logs the input found at `position` in the input file, must be called before applying it
+    pub fn append(&mut self, position: u64, input: &Input) -> Result<(), WalError> {
+        let record = WalRecord {
+            position,
+            input: input.clone(),
+        };
+        serde_json::to_writer(&mut self.log, &record)?;
+        self.log.write_all(b"\n")?;
+        self.log.flush()?;
+        self.position = position;
+        Ok(())
+    }
+
+    /// Writes the account state as the new checkpoint and empties the log
+    pub fn checkpoint(&mut self, accounts: &AccountStorage) -> Result<(), WalError> {
+        snapshot::save(accounts, self.position, &self.snapshot_path)?;
+        self.log = BufWriter::new(File::create(&self.log_path)?);
+        self.checkpoint_position = self.position;
+        Ok(())
+    }
+
+    /// Takes the last checkpoint of a run that read its input to the end. The next run starts
+    /// from these accounts at the start of its own input, as there is nothing left to skip
+    pub fn finish(&mut self, accounts: &AccountStorage) -> Result<(), WalError> {
+        self.position = 0;
+        self.checkpoint(accounts)
+    }
+
+    /// Takes a checkpoint if enough inputs have been logged since the last one
+    pub fn checkpoint_if_due(&mut self, accounts: &AccountStorage) -> Result<(), WalError> {
+        if self.position - self.checkpoint_position >= self.checkpoint_every {
+            self.checkpoint(accounts)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::TransactionType;
+    use std::collections::BTreeMap;
+
+    fn inputs() -> Vec<Input> {
+        vec![
+            Input::new(TransactionType::Deposit, 1, 1, Some(50.0)),
+            Input::new(TransactionType::Deposit, 2, 2, Some(20.0)),
+            Input::new(TransactionType::Withdrawal, 1, 3, Some(10.5)),
+            Input::new(TransactionType::Deposit, 1, 4, Some(5.1234)),
+            Input::new(TransactionType::Dispute, 1, 4, None),
+            Input::new(TransactionType::Withdrawal, 2, 5, Some(30.0)),
+            Input::new(TransactionType::Dispute, 2, 2, None),
+            Input::new(TransactionType::Resolve, 1, 4, None),
+            Input::new(TransactionType::Chargeback, 2, 2, None),
+            Input::new(TransactionType::Deposit, 2, 6, Some(1.0)),
+            Input::new(TransactionType::Deposit, 3, 7, Some(3.0)),
+            Input::new(TransactionType::Dispute, 1, 1, None),
+        ]
+    }
+
+    fn state(accounts: &AccountStorage) -> String {
+        let mut state = BTreeMap::new();
+        accounts
+            .accounts()
+            .for_each(&mut |client, account| {
+                state.insert(client, serde_json::to_value(account).unwrap());
+            })
+            .unwrap();
+        serde_json::to_string(&state).unwrap()
+    }
+
+    /// Processes the inputs after `from`, logging them in the wal as main does
+    fn process(
+        inputs: &[Input],
+        from: u64,
+        until: u64,
+        wal: &mut WriteAheadLog,
+        accounts: &mut AccountStorage,
+    ) {
+        for (i, input) in inputs.iter().enumerate() {
+            let position = i as u64 + 1;
+            if position <= from || position > until {
+                continue;
+            }
+            wal.append(position, input).unwrap();
+            let _res = accounts.apply(input.clone());
+            wal.checkpoint_if_due(accounts).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn wal_recovery_matches_clean_run() {
+        let inputs = inputs();
+        let total = inputs.len() as u64;
+
+        let mut clean = AccountStorage::new("");
+        for input in &inputs {
+            let _res = clean.apply(input.clone());
+        }
+        let expected = state(&clean);
+
+        // 0: killed between inputs, 1: killed after logging but before applying,
+        // 2: killed while writing a record, 3: killed between the snapshot and emptying the log
+        for mode in 0..4 {
+            for kill_at in 0..total {
+                let dir = std::env::temp_dir().join(format!("toypa_wal_{}_{}", mode, kill_at));
+                let dir = dir.to_str().unwrap();
+                let _ = fs::remove_dir_all(dir);
+
+                {
+                    let (mut wal, _) = WriteAheadLog::open(dir, "", 3).unwrap();
+                    let mut accounts = AccountStorage::new("");
+                    process(&inputs, 0, kill_at, &mut wal, &mut accounts);
+                    match mode {
+                        1 => wal.append(kill_at + 1, &inputs[kill_at as usize]).unwrap(),
+                        2 => {
+                            wal.log.write_all(b"{\"position\":").unwrap();
+                            wal.log.flush().unwrap();
+                        }
+                        3 => snapshot::save(&accounts, wal.position(), &wal.snapshot_path).unwrap(),
+                        _ => {}
+                    }
+                    // the process dies here, nothing is cleaned up
+                }
+
+                let (mut wal, recovered) = WriteAheadLog::open(dir, "", 3).unwrap();
+                let mut accounts = recovered
+                    .accounts
+                    .unwrap_or_else(|| AccountStorage::new(""));
+                for input in recovered.tail {
+                    let _res = accounts.apply(input);
+                }
+                let from = wal.position();
+                process(&inputs, from, total, &mut wal, &mut accounts);
+
+                assert_eq!(
+                    expected,
+                    state(&accounts),
+                    "mode {} killed at {}",
+                    mode,
+                    kill_at
+                );
+                fs::remove_dir_all(dir).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn wal_finished_run_skips_nothing() {
+        let dir = std::env::temp_dir().join("toypa_wal_finished");
+        let dir = dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(dir);
+        let inputs = inputs();
+
+        {
+            let (mut wal, _) = WriteAheadLog::open(dir, "", 5).unwrap();
+            let mut accounts = AccountStorage::new("");
+            process(&inputs, 0, 7, &mut wal, &mut accounts);
+            wal.finish(&accounts).unwrap();
+        }
+
+        // the next run, over another input, keeps the accounts but reads all of its input
+        let (mut wal, recovered) = WriteAheadLog::open(dir, "", 5).unwrap();
+        assert_eq!(0, wal.position());
+        assert!(recovered.tail.is_empty());
+        let mut accounts = recovered.accounts.unwrap();
+        process(&inputs[7..], 0, 5, &mut wal, &mut accounts);
+
+        let mut clean = AccountStorage::new("");
+        for input in &inputs {
+            let _res = clean.apply(input.clone());
+        }
+        assert_eq!(state(&clean), state(&accounts));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}